@@ -0,0 +1,218 @@
+// Template bundles, a zip archive with a manifest, a template's JSON and its asset files
+//
+// The layout of a bundle looks like the following:
+// manifest.toml
+// template.json
+// assets/
+//     *
+
+use crate::checksum::verify_template;
+use crate::dir::dir;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tokio::fs::{create_dir_all, read, remove_dir_all, write};
+use walkdir::WalkDir;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// The extension used for bundles
+pub const EXTENSION: &str = "texbundle";
+// The version of the bundle format, increased when it changes
+const FORMAT: u32 = 1;
+// The files that make up a bundle
+const MANIFEST: &str = "manifest.toml";
+const TEMPLATE: &str = "template.json";
+const ASSETS: &str = "assets";
+// The files every project is built with, which an asset can't replace, along with the main `.tex` file
+const RESERVED: &[&str] = &["compiler.toml", "include/structure.tex"];
+
+/// Describes the template and assets in a bundle
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    /// The version of the bundle format
+    pub format: u32,
+    pub name: String,
+    pub description: String,
+    pub version: String,
+    /// The path of each asset, relative to the project
+    pub assets: Vec<String>,
+}
+
+/// Packs a custom template and its assets into a bundle, returning the bundle's path
+///
+/// The assets are the given files and directories, or the ones the template was installed
+/// with if there are none. A file is placed in the project's root, while a directory places
+/// its contents in the project's root keeping their layout.
+pub async fn pack(name: &str, assets: &[PathBuf], output: Option<PathBuf>, force: bool) -> Result<PathBuf> {
+    let dir = dir()?;
+    let data = read(dir.template_path(name, "custom")?).await?;
+    let template = verify_template(name, &data, None)?;
+    let output = output.unwrap_or_else(|| PathBuf::from(format!("{name}.{EXTENSION}")));
+    if output.exists() && !force {
+        return Err(Error::AlreadyExists(output.display().to_string()));
+    }
+    let assets = if assets.is_empty() {
+        asset_files(&[dir.assets_path(name)?], true)?
+    } else {
+        asset_files(assets, false)?
+    };
+    if let Some((_, dest)) = assets.iter().find(|(_, dest)| is_reserved(Path::new(dest), None)) {
+        return Err(Error::ReservedAsset(dest.to_string()));
+    }
+    let manifest = Manifest {
+        format: FORMAT,
+        name: name.to_string(),
+        description: template.description.to_string(),
+        version: template.version.to_string(),
+        assets: assets.iter().map(|(_, dest)| dest.to_string()).collect(),
+    };
+    // need to use standard library `fs::File` since `ZipWriter` doesn't support `tokio::fs::File`
+    let mut writer = ZipWriter::new(File::create(&output)?);
+    let option = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut files = vec![
+        (MANIFEST.to_string(), toml::to_string(&manifest).unwrap().into_bytes()),
+        (TEMPLATE.to_string(), data),
+    ];
+    for (src, dest) in assets {
+        files.push((format!("{ASSETS}/{dest}"), read(&src).await?));
+    }
+    for (path, data) in files {
+        writer.start_file(path, option).map_err(zip_error)?;
+        writer.write_all(&data)?;
+    }
+    writer.finish().map_err(zip_error)?;
+    Ok(output)
+}
+
+/// Installs a bundle's template to the custom repo and its assets next to it, returning the template's name
+pub async fn install(bundle: &Path, name: Option<&str>, force: bool) -> Result<String> {
+    let invalid = || Error::InvalidBundle(bundle.display().to_string());
+    let mut archive = ZipArchive::new(File::open(bundle)?).map_err(|_| invalid())?;
+    let manifest: Manifest = toml::from_str(&read_entry(&mut archive, MANIFEST).map_err(|_| invalid())?)
+        .map_err(|_| invalid())?;
+    if manifest.format != FORMAT {
+        return Err(invalid());
+    }
+    let data = read_entry(&mut archive, TEMPLATE).map_err(|_| invalid())?;
+    let name = name.unwrap_or(&manifest.name).to_string();
+    // only valid templates are allowed in the custom repo
+    verify_template(&name, data.as_bytes(), None)?;
+    // read every asset the manifest lists before anything is saved
+    let mut assets = Vec::new();
+    for path in &manifest.assets {
+        let dest = safe_path(path).ok_or_else(invalid)?;
+        if is_reserved(&dest, None) {
+            return Err(Error::ReservedAsset(path.to_string()));
+        }
+        let asset = read_bytes(&mut archive, &format!("{ASSETS}/{path}")).map_err(|_| invalid())?;
+        assets.push((dest, asset))
+    }
+    let dir = dir()?;
+    // check the name before anything is saved
+    let assets_path = dir.assets_path(&name)?;
+    dir.save_custom(&name, data.as_bytes(), force).await?;
+    // replace the assets of a template with the same name
+    if assets_path.exists() {
+        remove_dir_all(&assets_path).await?;
+    }
+    for (dest, data) in assets {
+        let path = assets_path.join(dest);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).await?;
+        }
+        write(path, data).await?;
+    }
+    Ok(name)
+}
+
+/// Returns the assets installed with a custom template, as `(path relative to the project, data)`
+///
+/// An asset that would replace one of the files the project is built with gives an error.
+pub async fn installed_assets(name: &str, project: &str) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let mut files = Vec::new();
+    for (src, dest) in asset_files(&[dir()?.assets_path(name)?], true)? {
+        let dest = PathBuf::from(dest);
+        if is_reserved(&dest, Some(project)) {
+            return Err(Error::ReservedAsset(dest.display().to_string()));
+        }
+        files.push((dest, read(&src).await?))
+    }
+    Ok(files)
+}
+
+/// Checks if a file would replace one of the files every project is built with, including the
+/// main `.tex` file when the project's name is known
+pub fn is_reserved(dest: &Path, project: Option<&str>) -> bool {
+    let main = project.map(|p| PathBuf::from(format!("{p}.tex")));
+    RESERVED.iter().any(|r| dest == Path::new(r)) || main.is_some_and(|m| dest == m)
+}
+
+// Returns the source and destination of every asset file, where the destination uses `/` to separate paths
+fn asset_files(paths: &[PathBuf], optional: bool) -> Result<Vec<(PathBuf, String)>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_file() {
+            if let Some(name) = path.file_name() {
+                files.push((path.to_path_buf(), name.to_string_lossy().to_string()))
+            }
+            continue;
+        }
+        if !path.is_dir() {
+            // an installed template may not have any assets
+            if optional {
+                continue;
+            }
+            return Err(Error::InvalidPath(path.display().to_string()));
+        }
+        for entry in WalkDir::new(path).min_depth(1) {
+            let entry = entry.map_err(|_| Error::InvalidPath(path.display().to_string()))?;
+            if entry.path().is_dir() {
+                continue;
+            }
+            let relative: Vec<String> = entry
+                .path()
+                .strip_prefix(path)
+                .unwrap()
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect();
+            files.push((entry.path().to_path_buf(), relative.join("/")))
+        }
+    }
+    // two assets can't be copied to the same place
+    let mut dests: Vec<&String> = files.iter().map(|(_, dest)| dest).collect();
+    dests.sort();
+    if let Some(w) = dests.windows(2).find(|w| w[0] == w[1]) {
+        return Err(Error::AlreadyExists(w[0].to_string()));
+    }
+    Ok(files)
+}
+
+// Returns an asset's path if it stays inside of the directory it is extracted to
+fn safe_path(path: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(path);
+    let safe = path.components().all(|c| matches!(c, Component::Normal(_)));
+    (safe && !path.as_os_str().is_empty()).then_some(path)
+}
+
+// Reads an entry of a bundle as a string
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String> {
+    let bytes = read_bytes(archive, name)?;
+    String::from_utf8(bytes).map_err(|_| Error::InvalidInput(name.to_string()))
+}
+
+// Reads an entry of a bundle as bytes
+fn read_bytes(archive: &mut ZipArchive<File>, name: &str) -> Result<Vec<u8>> {
+    let mut file = archive.by_name(name).map_err(zip_error)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+// Turns a zip error into an IO error
+fn zip_error(e: zip::result::ZipError) -> Error {
+    Error::IO(e.into())
+}
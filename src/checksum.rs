@@ -0,0 +1,54 @@
+use crate::error::{Error, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use texcore::template::Template;
+use toml::{from_str, to_string, Table, Value};
+
+// The table in `repo.toml` that keeps the SHA-256 of each template
+const CHECKSUMS: &str = "checksums";
+
+/// Returns the hex encoded SHA-256 of some data
+pub fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Returns the `<Name, SHA-256>` of each template recorded in a `repo.toml` string
+///
+/// The `checksums` table is optional, so a manifest without one returns an empty map.
+pub fn read_checksums(repo: &str) -> BTreeMap<String, String> {
+    let mut checksums = BTreeMap::new();
+    let table: Table = match from_str(repo) {
+        Ok(t) => t,
+        Err(_) => return checksums,
+    };
+    if let Some(Value::Table(t)) = table.get(CHECKSUMS) {
+        for (name, value) in t {
+            if let Value::String(hash) = value {
+                checksums.insert(name.to_string(), hash.to_lowercase());
+            }
+        }
+    }
+    checksums
+}
+
+/// Returns a `repo.toml` string with its `checksums` table replaced
+pub fn write_checksums(repo: &str, checksums: &BTreeMap<String, String>) -> Result<String> {
+    let mut table: Table =
+        from_str(repo).map_err(|_| Error::InvalidInput("repo.toml".to_string()))?;
+    let mut t = Table::new();
+    for (name, hash) in checksums {
+        t.insert(name.to_string(), Value::String(hash.to_string()));
+    }
+    table.insert(CHECKSUMS.to_string(), Value::Table(t));
+    Ok(to_string(&table).unwrap())
+}
+
+/// Checks that a template's data matches its checksum (if there is one) and parses as a `Template`
+pub fn verify_template(name: &str, data: &[u8], checksum: Option<&String>) -> Result<Template> {
+    if let Some(expected) = checksum {
+        if &sha256(data) != expected {
+            return Err(Error::ChecksumMismatch(name.to_string()));
+        }
+    }
+    serde_json::from_slice(data).map_err(|_| Error::InvalidTemplateData(name.to_string()))
+}
@@ -1,14 +1,21 @@
 use std::fmt::Formatter;
+use crate::bundle::{installed_assets, is_reserved};
 use crate::cprint;
-use crate::dir::DIR;
+use crate::dir::dir;
 use crate::error::{Error, Result};
+use crate::language::Language;
+use crate::parameter::{resolve, value_to_string};
+use crate::repo::pinned_template;
+use crate::latex::{class_conflicts, packages, replace_document_class};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::io::{stdin, Write};
-use std::path::PathBuf;
-use termcolor::Color::Cyan;
+use std::path::{Component, Path, PathBuf};
+use termcolor::Color::{Cyan, Yellow};
 use texc_v3_compiler_conf::*;
 use texcore::{Any, Element, Input, Level, Metadata, Package};
-use tokio::fs::{create_dir, read_to_string};
+use walkdir::WalkDir;
+use tokio::fs::{create_dir, create_dir_all, read, read_to_string, write};
 use toml::{from_str, to_string_pretty};
 use zip::write::FileOptions;
 use zip::{CompressionMethod, ZipWriter};
@@ -22,6 +29,24 @@ pub struct Config {
     metadata: Metadata,
     // extra packages to put in a template
     packages: Vec<String>,
+    // the language settings of the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language: Option<Language>,
+    // overrides the template's document class
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    document_class: Option<String>,
+    // overrides the template's document class options, e.g. `["a4paper", "11pt", "twocolumn"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    class_options: Vec<String>,
+    // extra LaTeX code to put after the template's packages
+    #[serde(default, skip_serializing_if = "Preamble::is_empty")]
+    preamble: Preamble,
+    // local files and directories to copy into the project
+    #[serde(default, skip_serializing_if = "Assets::is_empty")]
+    assets: Assets,
+    // the answers to the parameters declared by the template
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    parameters: BTreeMap<String, toml::Value>,
 }
 
 // The default for Config, used when the user would like to use default settings
@@ -33,6 +58,12 @@ impl Default for Config {
             project,
             metadata,
             packages: vec![],
+            language: None,
+            document_class: None,
+            class_options: vec![],
+            preamble: Preamble::default(),
+            assets: Assets::default(),
+            parameters: BTreeMap::new(),
         }
     }
 }
@@ -41,11 +72,17 @@ impl Default for Config {
 /// - Project Name (Used for main directory, main source file, and `compiler.toml`)
 /// - The Template Name (Used to build the project using a particular template)
 /// - The Repo Name (Used to search which repo to find the template)
+/// - The Compiler (Optional, used in `compiler.toml` and to set up the document's language)
+/// - The Version (Optional, pins the mkproj repo version of the template, also written as `basic@7`)
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Project {
     proj_name: String,
     template: String,
     repo: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compiler: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<u64>,
 }
 
 // The default for Project, used when the user would like to use default settings
@@ -62,6 +99,8 @@ impl Project {
             proj_name: proj_name.to_string(),
             template: template.to_string(),
             repo: repo.to_string(),
+            compiler: None,
+            version: None,
         }
     }
     /// Prompts the user for Project settings
@@ -117,12 +156,53 @@ impl Project {
         let out_path = main_path.join("out");
         (main_path, incl_path, out_path)
     }
+    /// Returns the template name and the repo version it is pinned to, if any
+    ///
+    /// The version can be declared using `template = "basic@7"` or the `version` field.
+    pub fn template_ref(&self) -> Result<(String, Option<u64>)> {
+        let (name, version) = match self.template.split_once('@') {
+            Some((name, v)) => {
+                let v = v
+                    .parse::<u64>()
+                    .map_err(|_| Error::InvalidTemplate(self.template.to_string()))?;
+                (name, Some(v))
+            }
+            None => (self.template.as_str(), None),
+        };
+        // both ways of pinning must agree if they are used together
+        let version = match (version, self.version) {
+            (Some(a), Some(b)) if a != b => {
+                return Err(Error::InvalidTemplate(self.template.to_string()))
+            }
+            (a, b) => a.or(b),
+        };
+        Ok((name.to_string(), version))
+    }
+    /// Returns the name of the compiler used by the project (default: `pdflatex`)
+    pub fn compiler_name(&self) -> String {
+        self.compiler
+            .clone()
+            .unwrap_or_else(|| "pdflatex".to_string())
+    }
+    /// Returns the `Compiler` configuration of the project
+    pub fn compiler(&self) -> Compiler {
+        let compiler = Compiler::new(&self.proj_name);
+        match &self.compiler {
+            None => compiler,
+            // `Compiler` doesn't expose its fields, so we change the compiler through its TOML value
+            Some(name) => {
+                let mut value = toml::Value::try_from(&compiler).unwrap();
+                value["compiler"] = toml::Value::String(name.to_string());
+                value.try_into().unwrap()
+            }
+        }
+    }
     /// Creates the layout of a project
     pub async fn create_layout(&self) -> Result<()> {
         // get the main, include and out path from the `paths()` method
         let (main_path, incl_path, out_path) = self.paths();
-        // create a new `Compiler` type using the project name
-        let compiler = Compiler::new(&self.proj_name);
+        // create the `Compiler` type of the project
+        let compiler = self.compiler();
         // first create the main project path
         create_dir(&main_path).await?;
         // create the include and out directories
@@ -134,18 +214,176 @@ impl Project {
     }
 }
 
+/// The Preamble section provides the user to add their own LaTeX code to `include/structure.tex`:
+/// - Lines (Inline LaTeX code such as `\newcommand` macros)
+/// - Files (Paths to `.tex` snippets that are copied into `include/` and added using `\input{}`)
+///
+/// Both are placed after the template's packages and the language setup, with lines before files,
+/// and each in the order they are declared.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Preamble {
+    #[serde(default)]
+    lines: Vec<String>,
+    #[serde(default)]
+    files: Vec<PathBuf>,
+}
+
+impl Preamble {
+    /// Checks if there is no preamble to add
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.files.is_empty()
+    }
+    /// Returns the path of each snippet file and the file name it uses in `include/`
+    pub fn file_names(&self) -> Result<Vec<(&Path, String)>> {
+        let mut names: Vec<(&Path, String)> = Vec::new();
+        for path in &self.files {
+            // a snippet must be a `.tex` file that doesn't replace `structure.tex` or another snippet
+            let name = match (path.file_name(), path.extension()) {
+                (Some(name), Some(ext)) if ext == "tex" => name.to_string_lossy().to_string(),
+                _ => return Err(Error::InvalidPath(path.display().to_string())),
+            };
+            if name == "structure.tex" || names.iter().any(|(_, n)| n == &name) {
+                return Err(Error::InvalidPath(path.display().to_string()));
+            }
+            names.push((path, name))
+        }
+        Ok(names)
+    }
+    /// Returns the LaTeX code to add to `include/structure.tex`
+    pub fn to_latex_string(&self) -> Result<String> {
+        let mut lines = self.lines.clone();
+        for (_, name) in self.file_names()? {
+            // `\input{}` doesn't need the `.tex` extension
+            let name = name.trim_end_matches(".tex");
+            lines.push(format!(r"\input{{include/{name}}}"));
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+/// The Assets section provides the user to copy local files into the project:
+/// - Directory (The directory in the project to copy the assets into, default: `figures`)
+/// - Paths (Files, directories or glob patterns such as `plots/*.pdf`)
+///
+/// Files are copied using their file name, while directories have their contents copied.
+/// If any of the assets are images, `\graphicspath{}` is set to the assets directory.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Assets {
+    #[serde(default = "Assets::default_dir")]
+    dir: String,
+    #[serde(default)]
+    paths: Vec<String>,
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self {
+            dir: Self::default_dir(),
+            paths: vec![],
+        }
+    }
+}
+
+impl Assets {
+    // The default directory to copy assets into
+    fn default_dir() -> String {
+        "figures".to_string()
+    }
+    /// Checks if there are no assets to copy
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+    /// Returns the source path of every asset file and its path in the project
+    pub fn files(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let dir = PathBuf::from(&self.dir);
+        // the assets directory must stay inside of the project, and an empty one would give `\graphicspath{{/}}`
+        if self.dir.trim().is_empty() || dir.is_absolute() || dir.components().any(|c| c.as_os_str() == "..") {
+            return Err(Error::InvalidPath(self.dir.to_string()));
+        }
+        let mut files: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for pattern in &self.paths {
+            // each path may be a glob pattern, a plain path is a pattern matching itself
+            let matches: Vec<PathBuf> = glob::glob(pattern)
+                .map_err(|_| Error::InvalidPath(pattern.to_string()))?
+                .filter_map(|p| p.ok())
+                .collect();
+            if matches.is_empty() {
+                return Err(Error::InvalidPath(pattern.to_string()));
+            }
+            for path in matches {
+                if path.is_dir() {
+                    // copy the contents of a directory keeping its layout
+                    for entry in WalkDir::new(&path) {
+                        let entry = entry.map_err(|_| Error::InvalidPath(path.display().to_string()))?;
+                        if entry.path().is_dir() {
+                            continue;
+                        }
+                        let relative = entry.path().strip_prefix(&path).unwrap().to_path_buf();
+                        files.push((entry.path().to_path_buf(), dir.join(relative)))
+                    }
+                } else if let Some(name) = path.file_name() {
+                    let dest = dir.join(name);
+                    files.push((path, dest))
+                }
+            }
+        }
+        // two assets can't be copied to the same path
+        for (i, (src, dest)) in files.iter().enumerate() {
+            if files[..i].iter().any(|(_, d)| d == dest) {
+                return Err(Error::InvalidPath(src.display().to_string()));
+            }
+        }
+        Ok(files)
+    }
+    /// Returns the `\graphicspath{}` of the assets directory if any of the assets are images
+    pub fn graphics_path(&self) -> Result<Option<String>> {
+        let has_images = self.files()?.iter().any(|(_, dest)| {
+            let ext = dest
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            matches!(ext.as_str(), "png" | "jpg" | "jpeg" | "pdf" | "eps" | "svg")
+        });
+        // LaTeX paths always use `/` and the directory needs a trailing slash
+        let dir = self.dir.trim_end_matches('/');
+        Ok(has_images.then(|| format!(r"\graphicspath{{{{{dir}/}}}}")))
+    }
+}
+
 impl Config {
     /// Create a new `Config` by prompting the user
-    pub fn new() -> Result<Self> {
+    pub async fn new() -> Result<Self> {
         // Create new `Project` using the method `prompt_user()`
         let project = Project::prompt_user()?;
         // Use default `Metadata`
         let metadata = Metadata::default();
+        // Prompt the user for each parameter the chosen template declares
+        let mut parameters = BTreeMap::new();
+        let params = match (dir(), project.template_ref()) {
+            (Ok(dir), Ok((name, version))) => dir.search_parameters(&name, &project.repo, version).await,
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+        match params {
+            Ok(params) => {
+                for param in params {
+                    let value = param.prompt_user()?;
+                    parameters.insert(param.name().to_string(), value);
+                }
+            }
+            // the template may not be installed yet, so we can't know its parameters
+            Err(e) => cprint!(Yellow, "Skipping template parameters: {e}"),
+        }
         // return new `Config` wrapped in `Ok()` since the function returns a `Result`
         Ok(Self {
             project,
             metadata,
             packages: vec![],
+            language: None,
+            document_class: None,
+            class_options: vec![],
+            preamble: Preamble::default(),
+            assets: Assets::default(),
+            parameters,
         })
     }
     /// Creates a new `Config` by reading a file
@@ -158,10 +396,6 @@ impl Config {
     pub fn name(&self) -> String {
         self.project.proj_name.to_string()
     }
-    // Returns the template name
-    fn template(&self) -> String {
-        self.project.template.to_string()
-    }
     // Returns the repo name
     fn repo(&self) -> String {
         self.project.repo.to_string()
@@ -175,30 +409,123 @@ impl Config {
         }
         packages
     }
+    /// Returns the main and structure LaTeX strings of the project
+    ///
+    /// The template is found using the template and repo names, and all of the config's
+    /// settings are applied to it, so that `build` and `zip` produce the same files.
+    pub async fn latex_split(&self) -> Result<(String, String)> {
+        // Get the template by using the template and repo names, or the repo version it's pinned to
+        let (name, version) = self.project.template_ref()?;
+        let mut template = match version {
+            Some(num) if self.repo() == "mkproj" => pinned_template(&name, num).await?,
+            // only the mkproj repo has versions
+            Some(_) => return Err(Error::InvalidRepo(self.repo())),
+            None => dir()?.search(&name, &self.repo()).await?,
+        };
+        // every parameter the template declares needs a value, even if the config doesn't have it
+        let declared = dir()?.search_parameters(&name, &self.repo(), version).await?;
+        let parameters = resolve(&declared, &self.parameters)?;
+        // Change the template's metadata to config's
+        template.change_metadata(self.metadata.clone());
+        // Push the array of packages from config
+        template.push_element_array(self.packages()).await;
+        // Path for the source file's `\input{}` entry
+        let str_path = PathBuf::from("include").join("structure");
+        // Create a new input for our source file
+        let input = Input::new(str_path, Level::Meta);
+        // get the split latex strings using input
+        let (mut main_data, mut str_data) = template.to_latex_split_string(input).await;
+        // replace each parameter's `{{name}}` placeholder with its value
+        for (name, value) in &parameters {
+            let placeholder = format!("{{{{{name}}}}}");
+            let value = value_to_string(value);
+            main_data = main_data.replace(&placeholder, &value);
+            str_data = str_data.replace(&placeholder, &value);
+        }
+        // override the document class and its options if the config declares them
+        if self.document_class.is_some() || !self.class_options.is_empty() {
+            let options = self.class_options.join(",");
+            let options = (!self.class_options.is_empty()).then_some(options.as_str());
+            main_data = replace_document_class(&main_data, self.document_class.as_deref(), options);
+        }
+        // warn the user about any packages that don't work well with the class
+        let class = self
+            .document_class
+            .clone()
+            .unwrap_or_else(|| self.metadata.doc_class.to_string());
+        for (package, reason) in class_conflicts(&class, &packages(&str_data)) {
+            cprint!(Yellow, "Warning: `{package}` conflicts with `{class}`, {reason}");
+        }
+        // set up the document's language after the template's packages
+        if let Some(language) = &self.language {
+            let latex = language.to_latex_string(&self.project.compiler_name())?;
+            str_data.push('\n');
+            str_data.push_str(&latex);
+        }
+        // point `graphicx` to the assets directory when there are figures
+        if let Some(graphics_path) = self.assets.graphics_path()? {
+            if !packages(&str_data).iter().any(|p| p == "graphicx") {
+                str_data.push_str("\n\\usepackage{graphicx}");
+            }
+            str_data.push('\n');
+            str_data.push_str(&graphics_path);
+        }
+        // add the user's own preamble last so it may use anything loaded before it
+        if !self.preamble.is_empty() {
+            str_data.push('\n');
+            str_data.push_str(&self.preamble.to_latex_string()?);
+        }
+        Ok((main_data, str_data))
+    }
+    /// Returns the extra files of the project, as their path in the project and their contents
+    pub async fn extra_files(&self) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        let mut files = Vec::new();
+        // the preamble snippets are copied into `include/`
+        for (path, name) in self.preamble.file_names()? {
+            if !path.is_file() {
+                return Err(Error::InvalidPath(path.display().to_string()));
+            }
+            let data = read(path).await?;
+            files.push((PathBuf::from("include").join(name), data))
+        }
+        // the assets are copied into their directory
+        for (src, dest) in self.assets.files()? {
+            let data = read(&src).await?;
+            files.push((dest, data))
+        }
+        // a custom template installed from a bundle copies its assets into the project
+        if self.repo() == "custom" {
+            let (name, _) = self.project.template_ref()?;
+            files.extend(installed_assets(&name, &self.name()).await?)
+        }
+        check_extra_files(&files, &self.name())?;
+        Ok(files)
+    }
     /// Builds a TexCreate project
     pub async fn build(&self) -> Result<()> {
+        // Get the latex strings before creating anything, so an invalid config leaves no layout behind
+        let (main_data, str_data) = self.latex_split().await?;
+        let extra_files = self.extra_files().await?;
         // Create the project layout
         self.project.create_layout().await?;
         // Get the main and include directory path
         let (main_path, incl_path, _) = self.project.paths();
-        // Get the template by using the template and repo names
-        let mut template = DIR.search(&self.template(), &self.repo()).await?;
-        // Change the template's metadata to config's
-        template.change_metadata(self.metadata.clone());
-        // Push the array of packages from config
-        template.push_element_array(self.packages()).await;
         // Path for the main source file
         let main_path = main_path.join(format!("{}.tex", self.name()));
         // Path to write the structure file
         let incl_path = incl_path.join("structure.tex");
-        // Path for the source file's `\input{}` entry
-        let str_path = PathBuf::from("include").join("structure");
-        // Create a new input for our source file
-        let input = Input::new(str_path, Level::Meta);
-        // Write the tex files using the main and include path, as well as using `input`
-        template
-            .write_tex_files(main_path, incl_path, input)
-            .await?;
+        // Write the tex files using the main and include path
+        write(main_path, main_data.as_bytes()).await?;
+        write(incl_path, str_data.as_bytes()).await?;
+        // Write the extra files relative to the project's directory
+        let (main_path, _, _) = self.project.paths();
+        for (path, data) in extra_files {
+            let path = main_path.join(path);
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent).await?;
+            }
+            write(path, data).await?;
+        }
         Ok(())
     }
     /// Zips a TexCreate Project
@@ -220,18 +547,12 @@ impl Config {
         // create the paths for the main and structure file
         let main_path = format!("{}.tex", &self.name());
         let str_path = PathBuf::from("include").join("structure.tex");
-        // get the template by using the template and repo name
-        let mut template = DIR.search(&self.template(), &self.repo()).await?;
-        // change the metadata to the config's
-        template.change_metadata(self.metadata.clone());
-        // Push the array of packages from the config
-        template.push_element_array(self.packages()).await;
-        // Create an input by cloning `str_path` since it's just `include/structure.tex`
-        let input = Input::new(str_path.clone(), Level::Meta);
-        // get the split latex strings using input
-        let (main_data, str_data) = template.to_latex_split_string(input).await;
-        // create a new compiler config using the project name
-        let compiler = Compiler::new(&self.name());
+        // get the split latex strings with all of the config's settings applied
+        let (main_data, str_data) = self.latex_split().await?;
+        // get the extra files of the project
+        let extra_files = self.extra_files().await?;
+        // get the project's compiler config
+        let compiler = self.project.compiler();
         // get the compiler toml string
         // need to assign it to a binding or we create a temporary value then borrow it
         // if we directly use compiler.to_string().as_bytes()
@@ -263,6 +584,21 @@ impl Config {
         writer
             .write_all(compiler_data.as_bytes())
             .expect("Couldn't write to compiler.toml");
+
+        // add each extra file using its path in the project
+        for (path, data) in extra_files {
+            // zip archives always use `/` to separate paths
+            let name: Vec<String> = path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect();
+            writer
+                .start_file(name.join("/"), option)
+                .expect("Couldn't start extra file");
+            writer
+                .write_all(&data)
+                .expect("Couldn't write to extra file");
+        }
         // finish the last file and drop the writer using the `finish()` method
         // we will ignore the returned `File`, recommended not to append anymore data to it
         let _ = writer.finish().unwrap();
@@ -271,8 +607,57 @@ impl Config {
     }
 }
 
+// Checks that no extra file replaces a file the project is built with or another extra file
+fn check_extra_files(files: &[(PathBuf, Vec<u8>)], project: &str) -> Result<()> {
+    let mut seen: Vec<PathBuf> = Vec::new();
+    for (path, _) in files {
+        // `./figures/a.png` and `figures/a.png` are the same file
+        let path: PathBuf = path.components().filter(|c| matches!(c, Component::Normal(_))).collect();
+        if is_reserved(&path, Some(project)) {
+            return Err(Error::ReservedAsset(path.display().to_string()));
+        }
+        if seen.contains(&path) {
+            return Err(Error::InvalidPath(path.display().to_string()));
+        }
+        seen.push(path)
+    }
+    Ok(())
+}
+
 impl std::fmt::Display for Config{
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(&to_string_pretty(&self).unwrap())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(paths: &[&str]) -> Vec<(PathBuf, Vec<u8>)> {
+        paths.iter().map(|p| (PathBuf::from(p), vec![])).collect()
+    }
+
+    #[test]
+    fn extra_files_cant_replace_project_files() {
+        for path in ["include/structure.tex", "./include/structure.tex", "compiler.toml", "thesis.tex"] {
+            assert!(check_extra_files(&files(&[path]), "thesis").is_err(), "{path}");
+        }
+        assert!(check_extra_files(&files(&["figures/a.png", "include/macros.tex", "other.tex"]), "thesis").is_ok());
+    }
+
+    #[test]
+    fn extra_files_cant_share_a_path() {
+        let result = check_extra_files(&files(&["include/macros.tex", "./include/macros.tex"]), "thesis");
+        assert!(matches!(result, Err(Error::InvalidPath(_))));
+    }
+
+    #[test]
+    fn assets_dir_must_stay_in_the_project() {
+        for dir in ["", " ", "/tmp", "../figures"] {
+            let assets = Assets { dir: dir.to_string(), paths: vec![] };
+            assert!(assets.files().is_err(), "{dir:?}");
+        }
+        assert!(Assets::default().files().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+// A line based diff, used to compare the LaTeX rendered by two templates
+
+use crate::cprint;
+use termcolor::Color;
+
+// The number of unchanged lines to show around each change
+const CONTEXT: usize = 3;
+
+// A line in the diff, with its position in the old and new text
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// Prints a unified diff between two texts, returning `false` if they have the same lines
+pub fn print_unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> bool {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&old, &new);
+    let changes: Vec<usize> = (0..ops.len())
+        .filter(|&i| !matches!(ops[i], Op::Same(..)))
+        .collect();
+    if changes.is_empty() {
+        return false;
+    }
+    cprint!(Color::Magenta, "--- {old_name}");
+    cprint!(Color::Magenta, "+++ {new_name}");
+    // changes that are close to each other share a hunk, so their context isn't printed twice
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    for (start, end) in hunks {
+        let hunk = &ops[start..end];
+        // the line numbers start at the first line of the hunk in each text
+        let (mut old_start, mut new_start) = position(&ops, start);
+        let old_count = hunk.iter().filter(|op| !matches!(op, Op::Added(_))).count();
+        let new_count = hunk.iter().filter(|op| !matches!(op, Op::Removed(_))).count();
+        // an empty range gives the line before it, like `diff -u`
+        if old_count > 0 {
+            old_start += 1;
+        }
+        if new_count > 0 {
+            new_start += 1;
+        }
+        cprint!(Color::Cyan, "@@ -{old_start},{old_count} +{new_start},{new_count} @@");
+        for op in hunk {
+            match *op {
+                Op::Same(i, _) => cprint!(Color::White, " {}", old[i]),
+                Op::Removed(i) => cprint!(Color::Red, "-{}", old[i]),
+                Op::Added(j) => cprint!(Color::Green, "+{}", new[j]),
+            }
+        }
+    }
+    true
+}
+
+// Returns the lines of each text before the operation at `index`
+fn position(ops: &[Op], index: usize) -> (usize, usize) {
+    let old = ops[..index].iter().filter(|op| !matches!(op, Op::Added(_))).count();
+    let new = ops[..index].iter().filter(|op| !matches!(op, Op::Removed(_))).count();
+    (old, new)
+}
+
+// Finds the operations to turn `old` into `new` using the longest common subsequence of lines
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let (n, m) = (old.len(), new.len());
+    // `lcs[i][j]` is the length of the longest common subsequence of `old[i..]` and `new[j..]`
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            ops.push(Op::Same(i, j));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push(Op::Removed(i));
+            i += 1;
+        } else {
+            ops.push(Op::Added(j));
+            j += 1;
+        }
+    }
+    ops.extend((i..n).map(Op::Removed));
+    ops.extend((j..m).map(Op::Added));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_lines_keeps_the_common_lines() {
+        let ops = diff_lines(&["a", "b", "c"], &["a", "x", "c", "d"]);
+        assert_eq!(
+            ops,
+            [Op::Same(0, 0), Op::Removed(1), Op::Added(1), Op::Same(2, 2), Op::Added(3)]
+        );
+    }
+
+    #[test]
+    fn diff_lines_handles_empty_texts() {
+        assert_eq!(diff_lines(&[], &["a"]), [Op::Added(0)]);
+        assert_eq!(diff_lines(&["a"], &[]), [Op::Removed(0)]);
+        assert!(diff_lines(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn position_counts_the_lines_before_an_operation() {
+        let ops = diff_lines(&["a", "b", "c"], &["x", "a", "c"]);
+        // `x` was added and `b` removed before `c`
+        assert_eq!(position(&ops, 3), (2, 2));
+    }
+
+    #[test]
+    fn same_texts_have_no_diff() {
+        assert!(!print_unified_diff("a\nb", "a\nb", "old", "new"));
+        assert!(print_unified_diff("a\nb", "a\nc", "old", "new"));
+    }
+}
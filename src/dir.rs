@@ -1,107 +1,341 @@
 // Creates the directory to save all templates
 
+use crate::checksum;
+use crate::cprint;
 use crate::error::*;
+use crate::parameter::{self, Parameter};
+use crate::repo::pinned_template;
+use crate::search;
 use dirs::home_dir;
 use lazy_static::lazy_static;
-use std::path::PathBuf;
-use indicatif::{ProgressBar, ProgressStyle};
+use std::env::var_os;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use texcore::template::Template;
 use texcreate_repo::Repo;
-use tokio::fs::{create_dir, read_to_string, remove_dir_all, File};
+use termcolor::Color;
+use tokio::fs::{create_dir, create_dir_all, read, read_to_string, remove_dir_all, remove_file, rename, File};
 use tokio::io::AsyncWriteExt;
 use walkdir::WalkDir;
 
+/// The environment variable used to relocate the TexCreate home
+pub const HOME_VAR: &str = "TEXCREATE_HOME";
+
+// The home given by the global `--home` flag, which takes priority over the environment
+static HOME_FLAG: OnceLock<PathBuf> = OnceLock::new();
+
+/// Uses a different TexCreate home, this must be called before `dir()` is first used
+pub fn set_home(home: PathBuf) {
+    let _ = HOME_FLAG.set(home);
+}
+
 lazy_static! {
     // A global Dir value that will be lazily evaluated
     // Reason to use DIR is to avoid creating a new Dir in every function
     // Dir doesn't change values with all methods immutably borrowing it, hence a
     // lazily static value will be more efficient
-    pub static ref DIR: Dir = Dir::new();
+    // The home may not be found, so `None` is kept and turned into an error by `dir()`
+    static ref DIR: Option<Dir> = Dir::new().ok();
+}
+
+/// Returns the global `Dir`, or an error if the TexCreate home couldn't be found
+pub fn dir() -> Result<&'static Dir> {
+    DIR.as_ref().ok_or(Error::NoHome)
 }
 
 /// A type to navigate the `.texcreate` directory
 pub struct Dir {
-    /// The main directory will be located in `$HOME/.texcreate`
+    /// The main directory will be located in `$TEXCREATE_HOME`, `$HOME/.texcreate` or `$XDG_DATA_HOME/texcreate`
     pub main_dir: PathBuf,
+    /// The cache directory will be located in `main_dir/cache` or `$XDG_CACHE_HOME/texcreate`
+    pub cache: PathBuf,
     /// The mkproject repo directory will be located `main_dir/mkproj`
     pub mkproj: PathBuf,
+    /// The staging directory for updates will be located in `main_dir/mkproj.staging`
+    pub staging: PathBuf,
+    /// The previous version of the mkproject repo will be located in `main_dir/mkproj.previous`
+    pub previous: PathBuf,
+    /// Pinned versions of the mkproject repo will be located in `main_dir/versions/v{num}`
+    pub versions: PathBuf,
     /// The custom repo directory will be located in `main_dir/custom`
     pub custom: PathBuf,
+    /// The stores installed from git repositories will be located in `main_dir/stores/<store>`
+    pub stores: PathBuf,
 }
 
 impl Dir {
-    pub fn new() -> Self {
-        // Use the function `home_dir()` to get `$HOME` value
-        // If there is no `$HOME` path then we cannot create the directory and we should panic
-        // Which makes `unwrap()` call valid to use, if the path exists then we will join it with `.texcreate`
-        let main_dir = home_dir().unwrap().join(".texcreate");
+    /// Finds the TexCreate home in the following order:
+    /// - the global `--home` flag
+    /// - `$TEXCREATE_HOME`
+    /// - `$HOME/.texcreate`
+    /// - `$XDG_DATA_HOME/texcreate`, with the cache in `$XDG_CACHE_HOME/texcreate`
+    ///
+    /// If none of these are available, we can't create the directory and an error is returned.
+    pub fn new() -> Result<Self> {
+        // an environment variable that is set but empty is treated as unset
+        let env_path = |var: &str| var_os(var).filter(|v| !v.is_empty()).map(PathBuf::from);
+        let home = HOME_FLAG.get().cloned().or_else(|| env_path(HOME_VAR));
+        let (main_dir, cache) = if let Some(home) = home {
+            let cache = home.join("cache");
+            (home, cache)
+        } else if let Some(home) = home_dir() {
+            let main_dir = home.join(".texcreate");
+            let cache = main_dir.join("cache");
+            (main_dir, cache)
+        } else if let Some(data) = env_path("XDG_DATA_HOME") {
+            let main_dir = data.join("texcreate");
+            let cache = match env_path("XDG_CACHE_HOME") {
+                Some(cache) => cache.join("texcreate"),
+                None => main_dir.join("cache"),
+            };
+            (main_dir, cache)
+        } else {
+            return Err(Error::NoHome);
+        };
         // With the main path we can join it with `mkproj` to get the mkproject repo directory path
         let mkproj = main_dir.join("mkproj");
         // We can do the same as above but for the `custom` repo directory
         let custom = main_dir.join("custom");
-        Self {
+        // Updates are staged and the previous repo is kept next to `mkproj`, so they are on the same
+        // file system and can be swapped in by renaming
+        let staging = main_dir.join("mkproj.staging");
+        let previous = main_dir.join("mkproj.previous");
+        // Each repo version keeps its own copy of the templates
+        let versions = main_dir.join("versions");
+        // Each store installed from git has its own directory
+        let stores = main_dir.join("stores");
+        Ok(Self {
             main_dir,
+            cache,
             mkproj,
+            staging,
+            previous,
+            versions,
             custom,
-        }
+            stores,
+        })
     }
-    /// Create the layout of `.texcreate`
+    /// Create the layout of `.texcreate`, only creating what is missing so it can be used to repair it
     pub async fn build(&self) -> Result<()> {
-        // Begin by creating the main directory
-        create_dir(&self.main_dir).await?;
-        // After we can build the directory for `mkproj` and `custom` directory respectively
-        create_dir(&self.mkproj).await?;
-        create_dir(&self.custom).await?;
+        // Begin by creating the main directory, its parent may not exist when relocated
+        create_dir_all(&self.main_dir).await?;
+        // The cache directory may be outside of the main directory
+        create_dir_all(&self.cache).await?;
+        // After we can build the directory for `mkproj` and `custom` directory respectively,
+        // any templates already in them are kept
+        create_dir_all(&self.mkproj).await?;
+        create_dir_all(&self.custom).await?;
         Ok(())
     }
-    /// Saves an mkproject template given the filename and its data
-    pub async fn save_mkproj(&self, file_name: &str, data: &[u8]) -> Result<()> {
-        let file_name = file_name.to_string();
-        // To create the proper path, we will join the filename to the `mkproj` directory path
-        let path = self.mkproj.join(&file_name);
-        // With the proper path, we can create the template
-        let mut file = File::create(&path).await?;
-
-        let pb = ProgressBar::new(data.len() as u64);
-        pb.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} [{msg}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})").unwrap()
-            .progress_chars("#>-"));
-
-        // Set the progress bar's message to the file name
-        pb.set_message(file_name.clone());
-
-        // Write the chunks of bytes to the file
-        for chunk in data.chunks(1024){
-            file.write_all(chunk).await?;
-            pb.inc(chunk.len() as u64);
+    /// Removes everything TexCreate keeps in its home and cache, including the custom templates
+    ///
+    /// The home may be any directory given by `--home`, so only what TexCreate owns is removed,
+    /// and `settings.toml` is kept.
+    pub async fn reset(&self) -> Result<()> {
+        for file in ["repo.toml", "state.toml", "stores.toml"] {
+            let path = self.main_dir.join(file);
+            if path.is_file() {
+                remove_file(path).await?;
+            }
+        }
+        let dirs = [
+            &self.mkproj,
+            &self.staging,
+            &self.previous,
+            &self.versions,
+            &self.custom,
+            &self.stores,
+            &self.cache.join("git"),
+        ];
+        for path in dirs {
+            if path.exists() {
+                remove_dir_all(path).await?;
+            }
         }
-
-        pb.finish_with_message(file_name.clone());
-
         Ok(())
     }
-    /// Searches for a template given a name and repository to look in, and will return a `Template`
-    pub async fn search(&self, name: &str, repo: &str) -> Result<Template> {
+    /// Checks if the layout exists with a `repo.toml`
+    pub fn is_initialized(&self) -> bool {
+        self.mkproj.is_dir() && self.custom.is_dir() && self.repo_exists()
+    }
+    /// Checks if `repo.toml` can be read and every template it lists is in `mkproj`
+    pub async fn repo_installed(&self) -> bool {
+        match self.read_repo().await {
+            Ok(repo) => repo
+                .into_iter()
+                .all(|(name, _)| self.mkproj.join(format!("{name}.json")).is_file()),
+            Err(_) => false,
+        }
+    }
+    /// Returns the path of an mkproject template in the staging directory, which updates download to
+    pub fn staged_path(&self, name: &str) -> Result<PathBuf> {
+        // the names come from the downloaded `repo.toml`, so they can't be trusted to stay in staging
+        if !valid_name(name) {
+            return Err(Error::InvalidTemplate(name.to_string()));
+        }
+        Ok(self.staging.join(format!("{name}.json")))
+    }
+    /// Returns the path of a template given a name and repository to look in
+    pub fn template_path(&self, name: &str, repo: &str) -> Result<PathBuf> {
+        // a name like `../mkproj/basic` would leave the repo's directory
+        if !valid_name(name) {
+            return Err(Error::InvalidTemplate(name.to_string()));
+        }
         // to get the filename we will need to add the JSON extension
         let file_name = format!("{name}.json");
         // to get the proper path we will use a match statement on the parameter, `repo`
-        // if the repo isn't `custom` or `mkproj`, then we have an invalid repo and we will return an error
+        // if the repo isn't `custom`, `mkproj` or an installed store, then we have an invalid repo and we
+        // will return an error
         let path = {
             match repo {
                 "custom" => self.custom.join(file_name),
                 "mkproj" => self.mkproj.join(file_name),
+                _ if valid_name(repo) && self.stores.join(repo).is_dir() => {
+                    self.stores.join(repo).join(file_name)
+                }
                 _ => return Err(Error::InvalidRepo(repo.to_string())),
             }
         };
         // if the path doesn't exist, then we have an invalid template and we will return an error
+        // with the templates that have a similar name, in case of a typo
         if !path.exists() {
+            let suggestions = search::suggestions(name, &self.templates());
+            return Err(Error::TemplateNotFound(name.to_string(), suggestions));
+        }
+        Ok(path)
+    }
+    /// Returns the `(repo, name)` of every template in the `mkproj` and `custom` repos and the installed
+    /// stores, sorted by name
+    pub fn templates(&self) -> Vec<(String, String)> {
+        let mut repos = vec![
+            ("mkproj".to_string(), self.mkproj.to_path_buf()),
+            ("custom".to_string(), self.custom.to_path_buf()),
+        ];
+        for entry in WalkDir::new(&self.stores).min_depth(1).max_depth(1).into_iter().flatten() {
+            if entry.path().is_dir() {
+                repos.push((entry.file_name().to_string_lossy().to_string(), entry.into_path()))
+            }
+        }
+        let mut templates = Vec::new();
+        for (repo, path) in &repos {
+            // a missing repo just has no templates
+            for entry in WalkDir::new(path).min_depth(1).max_depth(1).into_iter().flatten() {
+                let path = entry.path();
+                if path.is_file() && path.extension().is_some_and(|e| e == "json") {
+                    let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                    templates.push((repo.to_string(), name));
+                }
+            }
+        }
+        templates.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        templates
+    }
+    /// Returns the path of a template in a version of the mkproject repo, making sure the name can't
+    /// leave the version's directory
+    pub fn version_path(&self, name: &str, num: u64) -> Result<PathBuf> {
+        if !valid_name(name) {
+            return Err(Error::InvalidTemplate(name.to_string()));
+        }
+        Ok(self.versions.join(format!("v{num}")).join(format!("{name}.json")))
+    }
+    /// Saves a template to a version of the mkproject repo given the version number, template name and its data
+    pub async fn save_version(&self, num: u64, name: &str, data: &[u8]) -> Result<()> {
+        let path = self.version_path(name, num)?;
+        // the version's directory is created the first time one of its templates is saved
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).await?;
+        }
+        let mut file = File::create(&path).await?;
+        file.write_all(data).await?;
+        Ok(())
+    }
+    /// Searches for a template given a name and repository to look in, and will return a `Template`
+    pub async fn search(&self, name: &str, repo: &str) -> Result<Template> {
+        // find the template's path, which returns an error if the template or repo is invalid
+        let path = self.template_path(name, repo)?;
+        // read the template ourselves, `Template::from_file()` panics on a broken JSON file
+        let data = read(&path).await?;
+        // return the template back, or an error naming it if it can't be parsed
+        serde_json::from_slice(&data).map_err(|_| Error::InvalidTemplate(name.to_string()))
+    }
+    /// Saves a custom template given its name and data, refusing to overwrite unless `force` is true
+    pub async fn save_custom(&self, name: &str, data: &[u8], force: bool) -> Result<()> {
+        let path = self.custom_path(name)?;
+        if path.exists() && !force {
+            return Err(Error::AlreadyExists(name.to_string()));
+        }
+        let mut file = File::create(&path).await?;
+        file.write_all(data).await?;
+        Ok(())
+    }
+    /// Removes a custom template given its name
+    pub async fn remove_custom(&self, name: &str) -> Result<()> {
+        // the name is checked to stay inside of `custom`
+        let path = self.template_path(name, "custom")?;
+        remove_file(path).await?;
+        // a template installed from a bundle keeps its assets next to it
+        let assets = self.assets_path(name)?;
+        if assets.exists() {
+            remove_dir_all(assets).await?;
+        }
+        Ok(())
+    }
+    /// Renames a custom template, refusing to overwrite unless `force` is true
+    pub async fn rename_custom(&self, from: &str, to: &str, force: bool) -> Result<()> {
+        // the name is checked to stay inside of `custom`
+        let from_path = self.template_path(from, "custom")?;
+        let to_path = self.custom_path(to)?;
+        if to_path.exists() && !force {
+            return Err(Error::AlreadyExists(to.to_string()));
+        }
+        rename(from_path, to_path).await?;
+        // the assets are renamed with their template, replacing any the new name had
+        let (from_assets, to_assets) = (self.assets_path(from)?, self.assets_path(to)?);
+        if to_assets.exists() {
+            remove_dir_all(&to_assets).await?;
+        }
+        if from_assets.exists() {
+            rename(from_assets, to_assets).await?;
+        }
+        Ok(())
+    }
+    /// Returns the directory of the assets installed with a custom template from a bundle, making sure
+    /// the name can't leave the `custom` directory
+    pub fn assets_path(&self, name: &str) -> Result<PathBuf> {
+        if !valid_name(name) {
             return Err(Error::InvalidTemplate(name.to_string()));
         }
-        // the template should exist after these checks and we can use the method `Template_from_file()`
-        // to get the value `Template`
-        let template = Template::from_file(path)?;
-        // return the template back wrapped in `Ok` since the function returns `Result<Template>`
-        Ok(template)
+        Ok(self.custom.join(format!("{name}.assets")))
+    }
+    // Returns the path of a custom template, making sure the name can't leave the `custom` directory
+    fn custom_path(&self, name: &str) -> Result<PathBuf> {
+        if !valid_name(name) {
+            return Err(Error::InvalidTemplate(name.to_string()));
+        }
+        Ok(self.custom.join(format!("{name}.json")))
+    }
+    /// Checks that a store's name can be used, it can't leave the `stores` directory or be a built-in repo
+    pub fn check_store_name(&self, name: &str) -> Result<()> {
+        if !valid_name(name) || name == "mkproj" || name == "custom" {
+            return Err(Error::InvalidRepo(name.to_string()));
+        }
+        Ok(())
+    }
+    /// Returns the parameters a template declares given a name, repository to look in and the repo
+    /// version it's pinned to
+    pub async fn search_parameters(&self, name: &str, repo: &str, version: Option<u64>) -> Result<Vec<Parameter>> {
+        let path = match version {
+            // the pinned version is fetched first if it isn't in the local store
+            Some(num) if repo == "mkproj" => {
+                pinned_template(name, num).await?;
+                self.version_path(name, num)?
+            }
+            // only the mkproj repo has versions
+            Some(_) => return Err(Error::InvalidRepo(repo.to_string())),
+            None => self.template_path(name, repo)?,
+        };
+        let s = read_to_string(&path).await?;
+        parameter::from_template_json(&s)
     }
     /// Reads from `main_dir/repo.toml` and returns Repo
     pub async fn read_repo(&self) -> Result<Repo> {
@@ -109,38 +343,200 @@ impl Dir {
         let path = self.main_dir.join("repo.toml");
         // read the file using `path`
         let s = read_to_string(&path).await?;
-        // get the `Repo` value using the `from_string()` method
-        let repo = Repo::from_string(&s);
+        // get the `Repo` value, a broken `repo.toml` gives an error instead of panicking
+        let repo: Repo =
+            toml::from_str(&s).map_err(|_| Error::InvalidInput("repo.toml".to_string()))?;
         Ok(repo)
     }
-    /// Walks trough the `custom` directory and prints out the templates in it
-    pub async fn read_custom_repo(&self) -> Result<()> {
-        for entry in WalkDir::new(&self.custom) {
-            let entry = entry.unwrap();
-            if entry.path().is_dir() {
-                continue;
+    /// Verifies the local store, returning the number of templates that failed
+    ///
+    /// Every template listed in `repo.toml` must exist, match its recorded SHA-256 and parse as a
+    /// `Template`, while custom templates only need to parse.
+    pub async fn verify(&self) -> Result<usize> {
+        let mut failed = 0;
+        let repo_text = read_to_string(self.main_dir.join("repo.toml")).await?;
+        let repo: Repo = toml::from_str(&repo_text)
+            .map_err(|_| Error::InvalidInput("repo.toml".to_string()))?;
+        let checksums = checksum::read_checksums(&repo_text);
+        // the templates to check, as `(repo, template name, checksum)`
+        let mut templates = Vec::new();
+        for (name, _) in repo.into_iter() {
+            let sum = checksums.get(&name).cloned();
+            templates.push(("mkproj", name, sum));
+        }
+        for entry in WalkDir::new(&self.custom).min_depth(1).max_depth(1) {
+            let entry = entry.map_err(|e| Error::IO(e.into()))?;
+            let path = entry.path();
+            if path.is_file() && path.extension().is_some_and(|e| e == "json") {
+                let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                templates.push(("custom", name, None));
             }
-            println!("{}", entry.file_name().to_str().unwrap())
         }
-        Ok(())
-    }
-    /// Saves `repo.toml` given a url to send a get request to
-    pub async fn save_repo(&self, url: &str) -> Result<()> {
-        let repo = Repo::get_repo(url).await;
-        let s = repo.to_string();
-        let path = self.main_dir.join("repo.toml");
-        let mut file = File::create(&path).await?;
-        file.write_all(s.as_bytes()).await?;
-        Ok(())
+        templates.sort();
+        for (repo, name, sum) in templates {
+            let path = match repo {
+                "custom" => self.custom.join(format!("{name}.json")),
+                _ => self.mkproj.join(format!("{name}.json")),
+            };
+            let result = match read(&path).await {
+                Ok(data) => checksum::verify_template(&name, &data, sum.as_ref()).map(|_| ()),
+                Err(_) => Err(Error::InvalidTemplate(name.to_string())),
+            };
+            match result {
+                Ok(_) if repo == "mkproj" && sum.is_none() => {
+                    cprint!(Color::Yellow, "[{repo}] {name}: OK (no recorded checksum)")
+                }
+                Ok(_) => cprint!(Color::Green, "[{repo}] {name}: OK"),
+                Err(e) => {
+                    failed += 1;
+                    cprint!(Color::Red, "[{repo}] {name}: {e}")
+                }
+            }
+        }
+        Ok(failed)
     }
     /// Check if `repo.toml` exits
     pub fn repo_exists(&self) -> bool{
         self.main_dir.join("repo.toml").exists()
     }
-    /// Clears the `mkproj` repo directory, used when updating to latest repo
-    pub async fn clear(&self) -> Result<()> {
-        remove_dir_all(&self.mkproj).await?;
-        create_dir(&self.mkproj).await?;
+    /// Creates an empty staging directory to download a new repo into
+    pub async fn begin_staging(&self) -> Result<()> {
+        // deal with anything left behind by an update or rollback that failed
+        self.recover_staging().await?;
+        create_dir(&self.staging).await?;
         Ok(())
     }
+    /// Cleans up the staging directory left behind by an update or rollback that failed partway
+    ///
+    /// Downloads that were never committed have no `repo.toml` and are removed, but a staging
+    /// directory with a `repo.toml` holds a whole repo version, which is put back instead of lost.
+    pub async fn recover_staging(&self) -> Result<()> {
+        if !self.staging.join("repo.toml").exists() {
+            return self.discard_staging().await;
+        }
+        // the swap may have stopped after moving `repo.toml` into `mkproj`
+        let repo = self.main_dir.join("repo.toml");
+        if !repo.exists() && self.mkproj.join("repo.toml").exists() {
+            rename(self.mkproj.join("repo.toml"), &repo).await?;
+        }
+        if !self.mkproj.exists() {
+            // the swap stopped after moving `mkproj` out of the way, so finish it
+            rename(&self.staging, &self.mkproj).await?;
+            rename(self.mkproj.join("repo.toml"), &repo).await?;
+        } else if !self.previous.exists() {
+            // the swap never started, so keep the repo as the previous version
+            rename(&self.staging, &self.previous).await?;
+        } else {
+            // there is nowhere to put it without losing a version
+            return Err(Error::StagingLeftover(self.staging.display().to_string()));
+        }
+        cprint!(Color::Yellow, "Recovered the repo left in `mkproj.staging` by an update or rollback that failed");
+        Ok(())
+    }
+    /// Removes the staging directory, used when an update fails
+    pub async fn discard_staging(&self) -> Result<()> {
+        if self.staging.exists() {
+            remove_dir_all(&self.staging).await?;
+        }
+        Ok(())
+    }
+    /// Swaps in the staged repo given the contents of its `repo.toml`, keeping the current repo as
+    /// the previous version
+    pub async fn commit_staging(&self, repo: &str) -> Result<()> {
+        // the staged `repo.toml` travels with its templates until it's swapped in
+        let mut file = File::create(self.staging.join("repo.toml")).await?;
+        file.write_all(repo.as_bytes()).await?;
+        // only a single previous version is kept
+        if self.previous.exists() {
+            remove_dir_all(&self.previous).await?;
+        }
+        self.swap(&self.staging, &self.previous).await
+    }
+    /// Restores the previous repo, keeping the current repo as the previous version
+    pub async fn rollback(&self) -> Result<()> {
+        self.recover_staging().await?;
+        if !self.previous.join("repo.toml").exists() {
+            return Err(Error::NoRollback);
+        }
+        // move the previous repo out of the way, so the current one can take its place
+        rename(&self.previous, &self.staging).await?;
+        self.swap(&self.staging, &self.previous).await
+    }
+    // Moves `mkproj` with its `repo.toml` to `old`, and `new` with its `repo.toml` to `mkproj`
+    async fn swap(&self, new: &Path, old: &Path) -> Result<()> {
+        let repo = self.main_dir.join("repo.toml");
+        if self.mkproj.exists() {
+            if repo.exists() {
+                rename(&repo, self.mkproj.join("repo.toml")).await?;
+            }
+            rename(&self.mkproj, old).await?;
+        }
+        rename(new, &self.mkproj).await?;
+        rename(self.mkproj.join("repo.toml"), &repo).await?;
+        Ok(())
+    }
+}
+
+/// Checks that a template or store name can be used as a file name without leaving its directory
+pub fn valid_name(name: &str) -> bool {
+    !(name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', ':', '@']))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns an empty home in the temporary directory, one per test so they can run in parallel
+    async fn test_dir(test: &str) -> Dir {
+        let main_dir = std::env::temp_dir().join(format!("texcreate-{test}-{}", std::process::id()));
+        let _ = remove_dir_all(&main_dir).await;
+        let dir = Dir {
+            cache: main_dir.join("cache"),
+            mkproj: main_dir.join("mkproj"),
+            staging: main_dir.join("mkproj.staging"),
+            previous: main_dir.join("mkproj.previous"),
+            versions: main_dir.join("versions"),
+            custom: main_dir.join("custom"),
+            stores: main_dir.join("stores"),
+            main_dir,
+        };
+        dir.build().await.unwrap();
+        dir
+    }
+
+    #[test]
+    fn names_cant_leave_their_directory() {
+        for name in ["", ".hidden", "../../victim", "a/b", "a\\b", "C:x", "a@b"] {
+            assert!(!valid_name(name), "`{name}` should be invalid");
+        }
+        assert!(valid_name("basic"));
+        assert!(valid_name("my-template_2"));
+    }
+
+    #[tokio::test]
+    async fn template_path_rejects_traversal() {
+        let dir = test_dir("traversal").await;
+        assert!(matches!(dir.template_path("../custom/x", "mkproj"), Err(Error::InvalidTemplate(_))));
+        assert!(matches!(dir.template_path("x", "../mkproj"), Err(Error::InvalidRepo(_))));
+        remove_dir_all(&dir.main_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn staged_path_rejects_traversal() {
+        let dir = test_dir("staged").await;
+        assert!(matches!(dir.staged_path("../../victim"), Err(Error::InvalidTemplate(_))));
+        assert_eq!(dir.staged_path("basic").unwrap(), dir.staging.join("basic.json"));
+        remove_dir_all(&dir.main_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn search_rejects_malformed_json() {
+        let dir = test_dir("malformed").await;
+        dir.save_custom("broken", b"{ not json", false).await.unwrap();
+        match dir.search("broken", "custom").await {
+            Err(Error::InvalidTemplate(name)) => assert_eq!(name, "broken"),
+            other => panic!("expected an invalid template, got {:?}", other.map(|_| ())),
+        }
+        remove_dir_all(&dir.main_dir).await.unwrap();
+    }
 }
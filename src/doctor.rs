@@ -0,0 +1,180 @@
+// Checks the TexCreate home and the LaTeX tools, printing how to fix any problems found
+
+use crate::checksum::{read_checksums, verify_template};
+use crate::cprint;
+use crate::dir::{dir, Dir};
+use crate::error::{Error, Result};
+use crate::repo::get_latest_num;
+use termcolor::Color;
+use texcreate_repo::Repo;
+use tokio::fs::{read, read_to_string};
+use tokio::process::Command;
+
+// The programs to look for, as `(program, required, fix)`
+const TOOLS: &[(&str, bool, &str)] = &[
+    ("pdflatex", true, "install a TeX distribution like TeX Live or MiKTeX and make sure it is on your PATH"),
+    ("xelatex", false, "install it with your TeX distribution to use `compiler = \"xelatex\"`"),
+    ("lualatex", false, "install it with your TeX distribution to use `compiler = \"lualatex\"`"),
+    ("latexmk", false, "install it using `tlmgr install latexmk` or your TeX distribution's package manager"),
+    ("biber", false, "install it using `tlmgr install biber` to build bibliographies with biblatex"),
+    ("kpsewhich", false, "install it with your TeX distribution to use `texcreate template lint`"),
+];
+
+// The result of a single check
+enum Check {
+    Ok(String),
+    // something that may cause problems later, with how to fix it
+    Warn(String, String),
+    // something that is broken, with how to fix it
+    Fail(String, String),
+}
+
+/// Runs every check, printing the results and returning the number of problems found
+pub async fn doctor() -> Result<usize> {
+    let mut checks = Vec::new();
+    cprint!(Color::Magenta, "======HOME======");
+    match dir() {
+        Ok(dir) => {
+            checks.extend(layout(dir));
+            // without a home there is no repo to check
+            if dir.main_dir.is_dir() {
+                checks.extend(repo(dir).await);
+            }
+        }
+        Err(e) => checks.push(Check::Fail(
+            e.to_string(),
+            "set `TEXCREATE_HOME` or use `--home <path>`, then run `texcreate init`".to_string(),
+        )),
+    }
+    let problems = print_checks(&checks);
+    cprint!(Color::Magenta, "======TOOLS======");
+    let tools = tools().await;
+    Ok(problems + print_checks(&tools))
+}
+
+// Prints each check and how to fix it, returning the number that failed
+fn print_checks(checks: &[Check]) -> usize {
+    let mut failed = 0;
+    for check in checks {
+        match check {
+            Check::Ok(msg) => cprint!(Color::Green, "[OK] {msg}"),
+            Check::Warn(msg, fix) => {
+                cprint!(Color::Yellow, "[WARN] {msg}");
+                cprint!(Color::Cyan, "       => {fix}");
+            }
+            Check::Fail(msg, fix) => {
+                failed += 1;
+                cprint!(Color::Red, "[FAIL] {msg}");
+                cprint!(Color::Cyan, "       => {fix}");
+            }
+        }
+    }
+    failed
+}
+
+// Checks every directory of the layout exists
+fn layout(dir: &Dir) -> Vec<Check> {
+    if !dir.main_dir.is_dir() {
+        return vec![Check::Fail(
+            format!("The TexCreate home `{}` doesn't exist", dir.main_dir.display()),
+            "run `texcreate init` to create it".to_string(),
+        )];
+    }
+    let mut checks = vec![Check::Ok(format!("Found the TexCreate home at `{}`", dir.main_dir.display()))];
+    for (name, path) in [("cache", &dir.cache), ("mkproj", &dir.mkproj), ("custom", &dir.custom)] {
+        if path.is_dir() {
+            checks.push(Check::Ok(format!("Found the `{name}` directory")))
+        } else {
+            checks.push(Check::Fail(
+                format!("The `{name}` directory `{}` is missing", path.display()),
+                "run `texcreate init` to create it, your custom templates are kept".to_string(),
+            ))
+        }
+    }
+    // an update or rollback that was interrupted leaves its staging directory behind
+    if dir.staging.join("repo.toml").exists() {
+        checks.push(Check::Warn(
+            "An update or rollback didn't finish, a repo was left in `mkproj.staging`".to_string(),
+            "run `texcreate update` or `texcreate update --rollback` to recover it".to_string(),
+        ))
+    } else if dir.staging.exists() {
+        checks.push(Check::Warn(
+            "An update didn't finish, its files were left in `mkproj.staging`".to_string(),
+            "run `texcreate update` again, the staging directory is replaced".to_string(),
+        ))
+    }
+    checks
+}
+
+// Checks `repo.toml` parses and every template it lists exists and loads
+async fn repo(dir: &Dir) -> Vec<Check> {
+    let update = "run `texcreate update` to download the repo again".to_string();
+    let path = dir.main_dir.join("repo.toml");
+    let Ok(repo_text) = read_to_string(&path).await else {
+        return vec![Check::Fail("`repo.toml` is missing".to_string(), update)];
+    };
+    let repo: Repo = match toml::from_str(&repo_text) {
+        Ok(r) => r,
+        Err(_) => return vec![Check::Fail("`repo.toml` couldn't be parsed".to_string(), update)],
+    };
+    let version = repo.version();
+    let mut checks = vec![Check::Ok(format!("`repo.toml` is repo v{version}"))];
+    let checksums = read_checksums(&repo_text);
+    let mut failed = Vec::new();
+    let mut count = 0;
+    for (name, _) in repo.into_iter() {
+        count += 1;
+        let path = dir.mkproj.join(format!("{name}.json"));
+        let result = match read(&path).await {
+            Ok(data) => verify_template(&name, &data, checksums.get(&name)).map(|_| ()),
+            Err(_) => {
+                failed.push(format!("The template `{name}` listed in `repo.toml` is missing"));
+                continue;
+            }
+        };
+        if let Err(e) = result {
+            failed.push(e.to_string())
+        }
+    }
+    if failed.is_empty() {
+        checks.push(Check::Ok(format!("All {count} mkproj template(s) exist and load")))
+    }
+    for msg in failed {
+        checks.push(Check::Fail(msg, update.to_string()))
+    }
+    // a newer repo may fix templates that are broken, so it is worth knowing about
+    match get_latest_num().await {
+        Ok(latest) if latest > version => checks.push(Check::Warn(
+            format!("Repo v{version} is out of date, the latest is v{latest}"),
+            "run `texcreate update` to get the latest templates".to_string(),
+        )),
+        Ok(_) => checks.push(Check::Ok("The repo is up to date".to_string())),
+        Err(Error::Offline) => {
+            checks.push(Check::Ok("Skipped checking for the latest repo while offline".to_string()))
+        }
+        Err(_) => checks.push(Check::Warn(
+            "Couldn't check for the latest repo".to_string(),
+            "check your internet connection and run `texcreate doctor` again".to_string(),
+        )),
+    }
+    checks
+}
+
+// Checks which LaTeX engines and tools are available with their versions
+async fn tools() -> Vec<Check> {
+    let mut checks = Vec::new();
+    for (program, required, fix) in TOOLS {
+        let output = Command::new(program).arg("--version").output().await;
+        let check = match output {
+            Ok(out) if out.status.success() => {
+                let stdout = String::from_utf8_lossy(&out.stdout);
+                let version = stdout.lines().next().unwrap_or_default().trim().to_string();
+                Check::Ok(format!("{program}: {version}"))
+            }
+            _ if *required => Check::Fail(format!("`{program}` wasn't found"), fix.to_string()),
+            _ => Check::Warn(format!("`{program}` wasn't found"), fix.to_string()),
+        };
+        checks.push(check)
+    }
+    checks
+}
@@ -11,17 +11,98 @@ pub enum Error {
     // This will occur if the user puts in a template that doesn't exist in a repo
     #[error("The Template `{0}` is Invalid!")]
     InvalidTemplate(String),
+    // This will occur if a template doesn't exist in a repo, suggesting templates with a similar name
+    #[error("template `{0}` not found{}", did_you_mean(.1))]
+    TemplateNotFound(String, Vec<String>),
     // This will occur if the user inputs text in a prompt that is invalid
     #[error("The input `{0}` is Invalid!")]
     InvalidInput(String),
-    // This will occur if the user puts in a repo that isn't `mkproj` or `custom`
-    #[error("The repo `{0}` is invalid, only `mkproj` or `custom` is allowed!")]
+    // This will occur if the user puts in a repo that isn't `mkproj`, `custom` or an installed store
+    #[error("The repo `{0}` is invalid, only `mkproj`, `custom` or an installed store is allowed!")]
     InvalidRepo(String),
+    // This will occur if a file or directory declared in a config doesn't exist or can't be used
+    #[error("The path `{0}` is invalid!")]
+    InvalidPath(String),
+    // This will occur if a template's data doesn't match the SHA-256 recorded for it
+    #[error("The template `{0}` doesn't match its checksum!")]
+    ChecksumMismatch(String),
+    // This will occur if a template's data can't be parsed as a `Template`
+    #[error("The template `{0}` couldn't be parsed!")]
+    InvalidTemplateData(String),
+    // This will occur if `texcreate verify` finds any templates that failed
+    #[error("{0} template(s) failed verification!")]
+    VerificationFailed(usize),
+    // This will occur if `texcreate template lint` finds any problems in a template
+    #[error("{0} problem(s) found in the template!")]
+    LintFailed(usize),
+    // This will occur if a program TexCreate runs, like `kpsewhich`, can't be started
+    #[error("Couldn't run `{0}`, make sure it is installed and in your PATH!")]
+    MissingProgram(String),
+    // This will occur if a bundle isn't a zip archive with a valid manifest, template and assets
+    #[error("The bundle `{0}` is invalid!")]
+    InvalidBundle(String),
+    // This will occur if a bundle's asset would replace a file every project is built with
+    #[error("The file `{0}` would replace a file the project is built with, rename it!")]
+    ReservedAsset(String),
+    // This will occur if `texcreate doctor` finds any problems
+    #[error("{0} problem(s) found, see above for how to fix them!")]
+    DoctorFailed(usize),
+    // This will occur if `texcreate update --rollback` is used without a previous repo
+    #[error("There is no previous repo to roll back to!")]
+    NoRollback,
+    // This will occur if a command would overwrite a template or file without `--force`
+    #[error("`{0}` already exists, use the `--force` flag to overwrite it!")]
+    AlreadyExists(String),
     #[error("TexCreate {0} is incompatible with latest repo!\nPlease upgrade to {1}...")]
     IncompatibleVersion(Version, Version),
+    // This will occur if cloning or fetching a git repository fails
+    #[error("Git Error: {0}")]
+    Git(#[from] git2::Error),
     // This will handle any IO Error
     #[error("IO Error")]
     IO(#[from] io::Error),
+    // This will occur if a command needs the TexCreate home but it isn't initialized
+    #[error("TexCreate isn't initialized, run `texcreate init` first!")]
+    NotInitialized,
+    // This will occur if there is no `$TEXCREATE_HOME`, `$HOME` or `$XDG_DATA_HOME` to find the TexCreate home
+    #[error("Couldn't find the TexCreate home - set `TEXCREATE_HOME` or use the `--home` flag.")]
+    NoHome,
+    // This will occur if the user doesn't confirm something that can't be undone
+    #[error("Cancelled, nothing was changed.")]
+    Cancelled,
+    // This will occur if a command needs the network while TexCreate is offline
+    #[error("Offline mode, the network is disabled - remove the `--offline` flag or set `offline = false` in `settings.toml`.")]
+    Offline,
     #[error("Connection timeout - try again or use the `--ignore` flag if available on command.")]
     Timeout,
+    // This will occur if a request is answered with an HTTP status that isn't a success
+    #[error("The request to `{0}` failed with HTTP status {1}")]
+    HttpStatus(String, u16),
+    // This will occur if a response's body can't be read
+    #[error("The response from `{0}` couldn't be read")]
+    BadBody(String),
+    // This will occur if a response isn't what was expected, like a version number that isn't a number
+    #[error("The response from `{0}` couldn't be parsed")]
+    ParseFailed(String),
+    // This will occur if a failed update or rollback left a repo in the staging directory that can't be put back
+    #[error("An update or rollback didn't finish, move `{0}` out of the TexCreate home to continue, it holds a repo version")]
+    StagingLeftover(String),
+    // This will occur if a parameter without a default is prompted for but stdin is closed
+    #[error("No value was given for the parameter `{0}` and there is no default")]
+    MissingParameter(String),
+    // This will occur if a template couldn't be downloaded, with the reason why
+    #[error("Couldn't download the template `{0}`: {1}")]
+    Download(String, Box<Error>),
+    // This will occur if `texcreate update --keep-going` skipped templates that failed to download
+    #[error("{0} template(s) couldn't be downloaded, run `texcreate update` again to retry")]
+    DownloadsFailed(usize),
+}
+
+// Formats the suggestions for a template that doesn't exist
+fn did_you_mean(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        return String::new();
+    }
+    let list: Vec<String> = suggestions.iter().map(|s| format!("`{s}`")).collect();
+    format!(", did you mean {}?", list.join(", "))
 }
@@ -0,0 +1,150 @@
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The language settings of a TexCreate project
+///
+/// Depending on the compiler, either `babel` (`pdflatex`, `latex`) or `polyglossia`
+/// (`xelatex`, `lualatex`) is used to set up the languages of the document.
+///
+/// ```toml
+/// [language]
+/// main = "english"
+/// others = ["french"]
+///
+/// [language.captions]
+/// contents = "Table of Contents"
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Language {
+    // the main language of the document
+    main: String,
+    // secondary languages used in the document
+    #[serde(default)]
+    others: Vec<String>,
+    // overrides for localized strings such as "Contents" or "References"
+    #[serde(default)]
+    captions: BTreeMap<String, String>,
+}
+
+impl Language {
+    /// Returns the LaTeX code to set up the document's languages given the compiler's name
+    pub fn to_latex_string(&self, compiler: &str) -> Result<String> {
+        // make sure each language is a valid name before it ends up in the document
+        for lang in std::iter::once(&self.main).chain(&self.others) {
+            if lang.is_empty() || !lang.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err(Error::InvalidInput(lang.to_string()));
+            }
+        }
+        // `polyglossia` is the recommended package for the unicode engines
+        let polyglossia = matches!(compiler, "xelatex" | "lualatex");
+        let mut lines = Vec::new();
+        let main = if polyglossia {
+            let main = polyglossia_name(&self.main);
+            lines.push(r"\usepackage{polyglossia}".to_string());
+            lines.push(format!(r"\setdefaultlanguage{{{main}}}"));
+            if !self.others.is_empty() {
+                let others: Vec<&str> = self.others.iter().map(|l| polyglossia_name(l)).collect();
+                lines.push(format!(r"\setotherlanguages{{{}}}", others.join(",")));
+            }
+            main
+        } else {
+            // babel uses the last language in its options as the main language
+            let main = babel_name(&self.main);
+            let mut options: Vec<&str> = self.others.iter().map(|l| babel_name(l)).collect();
+            options.push(main);
+            lines.push(format!(r"\usepackage[{}]{{babel}}", options.join(",")));
+            main
+        };
+        // the captions need to be added to the language's caption hook, otherwise
+        // the language package will reset them at `\begin{document}`
+        let hook = if polyglossia { r"\gappto" } else { r"\addto" };
+        for (key, value) in &self.captions {
+            let command = caption_command(key).ok_or(Error::InvalidInput(key.to_string()))?;
+            lines.push(format!(
+                r"{hook}\captions{main}{{\renewcommand{{\{command}}}{{{value}}}}}"
+            ));
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+// Returns the name `babel` uses for a language
+fn babel_name(lang: &str) -> &str {
+    match lang {
+        "german" => "ngerman",
+        "austrian" => "naustrian",
+        _ => lang,
+    }
+}
+
+// Returns the name `polyglossia` uses for a language
+fn polyglossia_name(lang: &str) -> &str {
+    match lang {
+        "ngerman" | "naustrian" | "austrian" => "german",
+        "american" | "british" | "UKenglish" | "USenglish" => "english",
+        "brazilian" | "brazil" => "portuguese",
+        _ => lang,
+    }
+}
+
+// Returns the LaTeX command of a localized string given its caption name
+fn caption_command(key: &str) -> Option<&'static str> {
+    let command = match key {
+        "contents" => "contentsname",
+        "references" => "refname",
+        "bibliography" => "bibname",
+        "abstract" => "abstractname",
+        "figure" => "figurename",
+        "table" => "tablename",
+        "list_of_figures" => "listfigurename",
+        "list_of_tables" => "listtablename",
+        "chapter" => "chaptername",
+        "part" => "partname",
+        "appendix" => "appendixname",
+        "index" => "indexname",
+        _ => return None,
+    };
+    Some(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn language(toml: &str) -> Language {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn babel_puts_the_main_language_last() {
+        let lang = language("main = \"german\"\nothers = [\"french\"]");
+        assert_eq!(lang.to_latex_string("pdflatex").unwrap(), r"\usepackage[french,ngerman]{babel}");
+    }
+
+    #[test]
+    fn polyglossia_is_used_by_unicode_engines() {
+        let lang = language("main = \"british\"\nothers = [\"ngerman\", \"french\"]");
+        assert_eq!(
+            lang.to_latex_string("lualatex").unwrap(),
+            "\\usepackage{polyglossia}\n\\setdefaultlanguage{english}\n\\setotherlanguages{german,french}"
+        );
+    }
+
+    #[test]
+    fn captions_use_the_language_hook() {
+        let lang = language("main = \"english\"\n[captions]\ncontents = \"Table of Contents\"");
+        let latex = lang.to_latex_string("pdflatex").unwrap();
+        assert!(latex.ends_with(r"\addto\captionsenglish{\renewcommand{\contentsname}{Table of Contents}}"));
+        let latex = lang.to_latex_string("xelatex").unwrap();
+        assert!(latex.ends_with(r"\gappto\captionsenglish{\renewcommand{\contentsname}{Table of Contents}}"));
+    }
+
+    #[test]
+    fn invalid_languages_and_captions_are_rejected() {
+        assert!(language("main = \"en}glish\"").to_latex_string("pdflatex").is_err());
+        assert!(language("main = \"\"").to_latex_string("pdflatex").is_err());
+        let lang = language("main = \"english\"\n[captions]\nunknown = \"x\"");
+        assert!(lang.to_latex_string("pdflatex").is_err());
+    }
+}
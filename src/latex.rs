@@ -0,0 +1,408 @@
+// Helpers to inspect and modify the LaTeX code generated from templates
+
+use std::io::Write;
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+
+/// Packages that are known to conflict with a document class, as `(classes, package, reason)`
+const CLASS_CONFLICTS: &[(&[&str], &str, &str)] = &[
+    (KOMA, "fancyhdr", "use `scrlayer-scrpage` for headers and footers instead"),
+    (KOMA, "titlesec", "use the KOMA-Script heading commands instead"),
+    (KOMA, "sectsty", "use `\\setkomafont` instead"),
+    (KOMA, "tocloft", "use the KOMA-Script `tocbasic` package instead"),
+    (MEMOIR, "titlesec", "memoir provides its own heading styles"),
+    (MEMOIR, "tocloft", "memoir already includes the `tocloft` features"),
+    (MEMOIR, "fancyhdr", "memoir provides its own page styles"),
+    (BEAMER, "geometry", "beamer sets its own page layout"),
+    (BEAMER, "hyperref", "beamer already loads `hyperref`"),
+    (BEAMER, "xcolor", "beamer already loads `xcolor`, use the class option `xcolor=...`"),
+    (BEAMER, "titlesec", "beamer doesn't use the standard sectioning commands"),
+];
+
+/// Packages that are known to conflict with each other, as `(package, package, reason)`
+const PACKAGE_CONFLICTS: &[(&str, &str, &str)] = &[
+    ("subfigure", "subcaption", "`subfigure` is obsolete, only use `subcaption`"),
+    ("subfig", "subcaption", "both define `\\subfloat` and `\\subref`, only use one"),
+    ("subfigure", "subfig", "`subfigure` is obsolete, only use `subfig`"),
+    ("natbib", "biblatex", "`biblatex` provides its own citation commands"),
+    ("cite", "biblatex", "`biblatex` provides its own citation commands"),
+    ("cite", "natbib", "`natbib` provides its own citation commands"),
+    ("titlesec", "sectsty", "both redefine the sectioning commands, only use one"),
+    ("fancyhdr", "scrlayer-scrpage", "both set the page style, only use one"),
+    ("amsthm", "ntheorem", "both define theorem environments, only use one"),
+    ("fullpage", "geometry", "both set the page layout, only use `geometry`"),
+    ("inputenc", "fontspec", "`inputenc` isn't used by engines that need `fontspec`"),
+    ("times", "mathptmx", "`times` is obsolete, only use `mathptmx` or `newtxtext`"),
+    ("caption2", "caption", "`caption2` is obsolete, only use `caption`"),
+];
+
+// The KOMA-Script classes
+const KOMA: &[&str] = &["scrartcl", "scrreprt", "scrbook", "scrlttr2"];
+// The memoir class
+const MEMOIR: &[&str] = &["memoir"];
+// The beamer class
+const BEAMER: &[&str] = &["beamer"];
+
+/// Returns the names of all packages loaded using `\usepackage` or `\RequirePackage`
+pub fn packages(latex: &str) -> Vec<String> {
+    let mut packages = Vec::new();
+    for line in latex.lines() {
+        // ignore anything after a comment
+        let line = strip_comment(line);
+        for command in [r"\usepackage", r"\RequirePackage"] {
+            let mut rest = line;
+            while let Some(i) = rest.find(command) {
+                rest = &rest[i + command.len()..];
+                // skip over any options given to the package
+                if rest.trim_start().starts_with('[') {
+                    match rest.find(']') {
+                        Some(end) => rest = &rest[end + 1..],
+                        None => break,
+                    }
+                }
+                let rest_trim = rest.trim_start();
+                if !rest_trim.starts_with('{') {
+                    continue;
+                }
+                let Some(end) = rest_trim.find('}') else { break };
+                // a single `\usepackage` may load many packages, e.g. `\usepackage{amsmath,amssymb}`
+                for name in rest_trim[1..end].split(',') {
+                    let name = name.trim();
+                    if !name.is_empty() {
+                        packages.push(name.to_string())
+                    }
+                }
+                rest = &rest_trim[end + 1..];
+            }
+        }
+    }
+    packages
+}
+
+/// Replaces the `\documentclass` of a main file, given an optional class and options
+///
+/// If `class` or `options` is `None`, the original value is kept.
+pub fn replace_document_class(latex: &str, class: Option<&str>, options: Option<&str>) -> String {
+    let mut lines = Vec::new();
+    let mut replaced = false;
+    for line in latex.lines() {
+        let trimmed = line.trim_start();
+        if replaced || !trimmed.starts_with(r"\documentclass") {
+            lines.push(line.to_string());
+            continue;
+        }
+        replaced = true;
+        let rest = &trimmed[r"\documentclass".len()..];
+        // get the original options and class
+        let (old_options, rest) = match rest.strip_prefix('[') {
+            Some(r) => match r.split_once(']') {
+                Some((o, r)) => (o, r),
+                None => ("", rest),
+            },
+            None => ("", rest),
+        };
+        let (old_class, trailing) = match rest.trim_start().strip_prefix('{') {
+            Some(r) => r.split_once('}').unwrap_or((r, "")),
+            None => ("", rest),
+        };
+        let class = class.unwrap_or(old_class);
+        let options = options.unwrap_or(old_options);
+        let line = if options.is_empty() {
+            format!(r"\documentclass{{{class}}}{trailing}")
+        } else {
+            format!(r"\documentclass[{options}]{{{class}}}{trailing}")
+        };
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// Returns the reason for every package known to conflict with the document class
+pub fn class_conflicts(class: &str, packages: &[String]) -> Vec<(String, &'static str)> {
+    let mut conflicts = Vec::new();
+    for (classes, package, reason) in CLASS_CONFLICTS {
+        if classes.contains(&class) && packages.iter().any(|p| p == package) {
+            conflicts.push((package.to_string(), *reason))
+        }
+    }
+    conflicts
+}
+
+/// Returns the reason for every pair of packages known to conflict with each other
+pub fn package_conflicts(packages: &[String]) -> Vec<(String, String, &'static str)> {
+    let mut conflicts = Vec::new();
+    for (a, b, reason) in PACKAGE_CONFLICTS {
+        if packages.iter().any(|p| p == a) && packages.iter().any(|p| p == b) {
+            conflicts.push((a.to_string(), b.to_string(), *reason))
+        }
+    }
+    conflicts
+}
+
+/// The parts of a LaTeX document, used to import a document as a template
+#[derive(Debug, Default)]
+pub struct TexDocument {
+    /// The document class
+    pub class: String,
+    /// The options given to the document class
+    pub class_options: Vec<String>,
+    /// Each command in the preamble, with commands spanning many lines kept together
+    pub preamble: Vec<String>,
+    /// Everything between `\begin{document}` and `\end{document}`
+    pub body: String,
+}
+
+/// Parses a LaTeX document into its class, preamble commands and body
+///
+/// Returns `None` if there is no `\documentclass` or `\begin{document}`.
+pub fn parse_document(latex: &str) -> Option<TexDocument> {
+    let mut document = TexDocument::default();
+    let mut statement = String::new();
+    let (mut braces, mut brackets) = (0, 0);
+    let mut found_class = false;
+    let mut lines = latex.lines();
+    for line in lines.by_ref() {
+        let stripped = strip_comment(line);
+        // a `%` right after some code hides the line break, so it needs to be kept
+        let hides_break = stripped.len() < line.len()
+            && !stripped.trim().is_empty()
+            && !stripped.ends_with(char::is_whitespace);
+        let line = if hides_break {
+            &line[..=stripped.len()]
+        } else {
+            stripped.trim_end()
+        };
+        if line.trim_start().starts_with(r"\begin{document}") {
+            break;
+        }
+        if line.trim().is_empty() && braces == 0 && brackets == 0 {
+            continue;
+        }
+        // keep adding lines until every brace and option bracket is closed
+        if !statement.is_empty() {
+            statement.push('\n');
+        }
+        statement.push_str(line);
+        group_depth(line, &mut braces, &mut brackets);
+        if braces > 0 || brackets > 0 {
+            continue;
+        }
+        (braces, brackets) = (0, 0);
+        let s = std::mem::take(&mut statement);
+        match command_args(&s, r"\documentclass") {
+            Some((options, class)) if !found_class => {
+                found_class = true;
+                document.class = class;
+                document.class_options = split_options(&options.unwrap_or_default());
+            }
+            _ => document.preamble.push(s),
+        }
+    }
+    // the document must have a class and a body
+    let mut body = Vec::new();
+    let mut ended = false;
+    for line in lines {
+        if line.trim_start().starts_with(r"\end{document}") {
+            ended = true;
+            break;
+        }
+        body.push(line);
+    }
+    if !found_class || !ended {
+        return None;
+    }
+    document.body = body.join("\n").trim().to_string();
+    Some(document)
+}
+
+/// Returns the optional argument and first argument of a command at the start of a statement,
+/// for example `\usepackage[utf8]{inputenc}` returns `(Some("utf8"), "inputenc")`
+pub fn command_args(statement: &str, command: &str) -> Option<(Option<String>, String)> {
+    let rest = statement.trim_start().strip_prefix(command)?;
+    // make sure we didn't match the start of a longer command, like `\title` in `\titleformat`
+    if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let (options, rest) = match rest.strip_prefix('[') {
+        Some(r) => {
+            let (o, r) = r.split_once(']')?;
+            (Some(o.trim().to_string()), r.trim_start())
+        }
+        None => (None, rest),
+    };
+    let rest = rest.strip_prefix('{')?;
+    // find the closing brace of the argument, which may contain its own braces
+    let mut depth = 1;
+    for (i, c) in rest.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return Some((options, rest[..i].trim().to_string()));
+        }
+    }
+    None
+}
+
+/// Splits a comma separated list of options, e.g. `a4paper, 11pt`
+pub fn split_options(options: &str) -> Vec<String> {
+    options
+        .split(',')
+        .map(|o| o.trim().to_string())
+        .filter(|o| !o.is_empty())
+        .collect()
+}
+
+// Removes a comment from a line, keeping escaped percent signs (`\%`)
+fn strip_comment(line: &str) -> &str {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            '%' if !escaped => return &line[..i],
+            _ => escaped = false,
+        }
+    }
+    line
+}
+
+// Adds the braces and option brackets a line leaves open to the running depths, ignoring
+// escaped ones. Brackets only count outside of braces, since a `[` in an argument is just text.
+fn group_depth(line: &str, braces: &mut i64, brackets: &mut i64) {
+    let mut escaped = false;
+    for c in line.chars() {
+        match c {
+            '\\' => {
+                escaped = !escaped;
+                continue;
+            }
+            '{' if !escaped => *braces += 1,
+            '}' if !escaped => *braces -= 1,
+            '[' if !escaped && *braces <= 0 => *brackets += 1,
+            // a stray `]` shouldn't make the next `[` look closed
+            ']' if !escaped && *braces <= 0 && *brackets > 0 => *brackets -= 1,
+            _ => {}
+        }
+        escaped = false;
+    }
+}
+
+/// Prints LaTeX code with syntax highlighting for commands, brackets, math and comments
+pub fn print_highlighted(latex: &str) {
+    let mut stdout = StandardStream::stdout(ColorChoice::Auto);
+    let mut spec = ColorSpec::new();
+    for line in latex.lines() {
+        let code = strip_comment(line);
+        let comment = &line[code.len()..];
+        let mut chars = code.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            let (color, end) = match c {
+                '\\' => {
+                    let mut end = i + 1;
+                    // a command is a backslash followed by letters, or by a single symbol like `\%`
+                    match chars.peek().copied() {
+                        Some((_, n)) if n.is_ascii_alphabetic() => {
+                            while let Some((j, n)) = chars.peek().copied() {
+                                if !n.is_ascii_alphabetic() {
+                                    break;
+                                }
+                                end = j + n.len_utf8();
+                                chars.next();
+                            }
+                        }
+                        Some((j, n)) if !n.is_whitespace() => {
+                            end = j + n.len_utf8();
+                            chars.next();
+                        }
+                        _ => {}
+                    }
+                    (Some(Color::Cyan), end)
+                }
+                '{' | '}' | '[' | ']' => (Some(Color::Yellow), i + 1),
+                '$' => (Some(Color::Magenta), i + 1),
+                _ => (None, i + c.len_utf8()),
+            };
+            let _ = stdout.set_color(spec.set_fg(color));
+            let _ = write!(&mut stdout, "{}", &code[i..end]);
+        }
+        let _ = stdout.set_color(spec.set_fg(Some(Color::Green)));
+        let _ = writeln!(&mut stdout, "{comment}");
+    }
+    let _ = stdout.reset();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packages_reads_options_lists_and_comments() {
+        let latex = "\\usepackage[utf8]{inputenc}\n\\usepackage{amsmath, amssymb}\n% \\usepackage{hidden}\n\\RequirePackage{xcolor}";
+        assert_eq!(packages(latex), ["inputenc", "amsmath", "amssymb", "xcolor"]);
+    }
+
+    #[test]
+    fn replace_document_class_keeps_what_isnt_given() {
+        let latex = "\\documentclass[11pt]{article}\n\\begin{document}";
+        assert_eq!(
+            replace_document_class(latex, Some("report"), None),
+            "\\documentclass[11pt]{report}\n\\begin{document}"
+        );
+        assert_eq!(
+            replace_document_class(latex, None, Some("a4paper")),
+            "\\documentclass[a4paper]{article}\n\\begin{document}"
+        );
+    }
+
+    #[test]
+    fn conflicts_are_found() {
+        let found = class_conflicts("scrartcl", &["fancyhdr".to_string()]);
+        assert_eq!(found.len(), 1);
+        assert!(class_conflicts("article", &["fancyhdr".to_string()]).is_empty());
+        let found = package_conflicts(&["natbib".to_string(), "biblatex".to_string()]);
+        assert_eq!(found[0].0, "natbib");
+    }
+
+    #[test]
+    fn parse_document_splits_class_preamble_and_body() {
+        let latex = "\\documentclass[a4paper, 11pt]{article}\n\\usepackage{amsmath} % math\n\\newcommand{\\R}{\n  \\mathbb{R}\n}\n\\begin{document}\nHello\n\\end{document}";
+        let document = parse_document(latex).unwrap();
+        assert_eq!(document.class, "article");
+        assert_eq!(document.class_options, ["a4paper", "11pt"]);
+        assert_eq!(document.preamble, ["\\usepackage{amsmath}", "\\newcommand{\\R}{\n  \\mathbb{R}\n}"]);
+        assert_eq!(document.body, "Hello");
+    }
+
+    #[test]
+    fn parse_document_reads_multi_line_class_options() {
+        let latex = "\\documentclass[\n  a4paper,\n  11pt\n]{article}\n\\usepackage{amsmath}\n\\begin{document}\n\\end{document}";
+        let document = parse_document(latex).unwrap();
+        assert_eq!(document.class, "article");
+        assert_eq!(document.class_options, ["a4paper", "11pt"]);
+        assert_eq!(document.preamble, ["\\usepackage{amsmath}"]);
+    }
+
+    #[test]
+    fn parse_document_ignores_brackets_in_arguments() {
+        let latex = "\\documentclass{article}\n\\newcommand{\\open}{[}\n\\usepackage{amsmath}\n\\begin{document}\n\\end{document}";
+        let document = parse_document(latex).unwrap();
+        assert_eq!(document.preamble, ["\\newcommand{\\open}{[}", "\\usepackage{amsmath}"]);
+    }
+
+    #[test]
+    fn parse_document_needs_a_class_and_body() {
+        assert!(parse_document("\\usepackage{amsmath}\n\\begin{document}\n\\end{document}").is_none());
+        assert!(parse_document("\\documentclass{article}\n\\begin{document}\n").is_none());
+    }
+
+    #[test]
+    fn command_args_reads_nested_braces() {
+        assert_eq!(
+            command_args("\\title[short]{A {B} C}", "\\title"),
+            Some((Some("short".to_string()), "A {B} C".to_string()))
+        );
+        assert_eq!(command_args("\\titleformat{x}", "\\title"), None);
+    }
+}
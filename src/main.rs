@@ -1,16 +1,28 @@
+mod bundle;
+mod checksum;
 mod config;
+mod diff;
 mod dir;
+mod doctor;
 mod error;
+mod language;
+mod latex;
+mod parameter;
 mod repo;
+mod search;
+mod settings;
+mod store;
+mod template;
 mod texc_gen;
 mod auto_complete;
 
 use crate::config::Config;
+use crate::template::{show_template, templates_display, TemplateCommands};
 use crate::texc_gen::Commands;
-use dir::Dir;
+use dir::dir;
 use error::*;
 use repo::*;
-use std::io::stdin;
+use std::io::{stdin, IsTerminal};
 use std::path::PathBuf;
 use structopt::StructOpt;
 use termcolor::Color;
@@ -35,9 +47,24 @@ macro_rules! cprint {
 
 #[derive(StructOpt)]
 #[structopt(name = "TexCreate", about = "A LaTeX Project Creator by Mustafif Khan")]
-pub enum Cli {
-    #[structopt(about = "Initialize TexCreate.")]
-    Init,
+pub struct Cli {
+    #[structopt(long, global = true, parse(from_os_str), help = "Use a different TexCreate home directory.")]
+    home: Option<PathBuf>,
+    #[structopt(long, global = true, help = "Don't make any requests to the template repo.")]
+    offline: bool,
+    #[structopt(subcommand)]
+    action: Action,
+}
+
+#[derive(StructOpt)]
+pub enum Action {
+    #[structopt(about = "Initialize TexCreate, or repair it by creating anything missing.")]
+    Init {
+        #[structopt(long, help = "Remove everything, including custom templates, and initialize again.")]
+        reset: bool,
+        #[structopt(short, long, help = "Don't ask for confirmation before resetting.")]
+        yes: bool,
+    },
     #[structopt(about = "Create a new project's config file.")]
     New{
         #[structopt(short, long)]
@@ -58,14 +85,35 @@ pub enum Cli {
         ignore: Option<bool>
     },
     #[structopt(about = "Updates to the latest MKProject templates.")]
-    Update,
+    Update {
+        #[structopt(long, help = "Restores the templates replaced by the last update.")]
+        rollback: bool,
+        #[structopt(long, help = "Skips templates that fail to download instead of aborting the update.")]
+        keep_going: bool,
+    },
+    #[structopt(about = "Verifies the integrity of the local templates.")]
+    Verify,
+    #[structopt(about = "Checks the TexCreate home and LaTeX tools, and shows how to fix any problems.")]
+    Doctor,
     #[structopt(about = "Updates TexCreate (`cargo`) and templates to the latest version.")]
     Upgrade,
     #[structopt(about = "Shows all available templates (default MKProj).")]
     List {
         #[structopt(short, long)]
         repo: Option<String>,
+        #[structopt(long, help = "Print the custom templates as JSON.")]
+        json: bool,
+    },
+    #[structopt(about = "Shows the rendered LaTeX of a template without creating a project.")]
+    Show {
+        template: String,
+        #[structopt(short, long)]
+        repo: Option<String>,
     },
+    #[structopt(about = "Searches the names and descriptions of all templates.")]
+    Search { query: String },
+    #[structopt(about = "Manages the templates in the custom repo.")]
+    Template(TemplateCommands),
     #[structopt(about = "Compiles a TexCreate project.")]
     Compile,
     #[structopt(about = "Runs a TexcGen Project.")]
@@ -89,44 +137,54 @@ pub enum Cli {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::from_args();
-    match cli {
-        Cli::Init => {
-            // initializes the texcreate directory
-            // and gets the latest repo
-            let dir = Dir::new();
-            // creates the layout
-            cprint!(
-                Color::Magenta,
-                "Creating TeXCreate directory layout at: {}",
-                dir.main_dir.display()
-            );
-            let layout_task = spawn(async move { dir.build().await }).await.ok();
-            match layout_task {
-                None => {
-                    cprint!(Color::Red, "Failed to build TexCreate directory!");
-                    return Ok(());
-                }
-                Some(r) => r?,
-            }
-            // gets the latest repo for mkproj
-            let update_task = spawn(async move { repo_update().await }).await.ok();
-            match update_task {
-                None => {
-                    cprint!(Color::Red, "Failed to update to latest repo!");
-                    return Ok(());
-                }
-                Some(r) => r?,
+async fn main() {
+    // errors are shown with their message rather than the `Debug` output of returning them
+    if let Err(e) = run(Cli::from_args()).await {
+        cprint!(Color::Red, "Error: {e}");
+        std::process::exit(1);
+    }
+}
+
+// Runs the command given to the CLI
+async fn run(cli: Cli) -> Result<()> {
+    // the `--home` flag takes priority over the environment, this must be set before `dir()` is used
+    if let Some(home) = &cli.home {
+        dir::set_home(home.clone());
+    }
+    // the `--offline` flag is read with the `offline` setting by `settings::is_offline()`
+    if cli.offline {
+        settings::set_offline();
+    }
+    // commands that use the templates need TexCreate to be initialized
+    let needs_home = matches!(
+        cli.action,
+        Action::New { .. }
+            | Action::Build { .. }
+            | Action::Zip { .. }
+            | Action::Update { .. }
+            | Action::Verify
+            | Action::Upgrade
+            | Action::List { .. }
+            | Action::Show { .. }
+            | Action::Search { .. }
+            | Action::Template(_)
+    );
+    // whether the home was just initialized, which already downloaded the latest repo
+    let initialized = needs_home && ensure_init().await?;
+    match cli.action {
+        Action::Init { reset, yes } => {
+            if reset {
+                reset_home(yes).await?;
             }
+            init().await?
         }
-        Cli::New{ignore} => {
+        Action::New{ignore} => {
             // checks to see if there is a new template
             if ignore == Some(false) || ignore.is_none(){
                 alert().await;
             }
             // prompts the user to create a new config
-            let config = Config::new()?;
+            let config = Config::new().await?;
             // get the TOML string
             let s = config.to_string();
             let mut file_name = String::new();
@@ -153,7 +211,7 @@ async fn main() -> Result<()> {
             // let the user know the project has successfully been created
             cprint!(Color::Green, "Successfully created `{}`", file_name);
         }
-        Cli::Build { file, ignore } => {
+        Action::Build { file, ignore } => {
             // checks to see if there is a new template
             if ignore == Some(false) || ignore.is_none(){
                 alert().await;
@@ -176,7 +234,7 @@ async fn main() -> Result<()> {
             }
             cprint!(Color::Green, "Successfully created `{}`", name);
         }
-        Cli::Zip { file, ignore } => {
+        Action::Zip { file, ignore } => {
             // checks to see if there is a new template
             if ignore == Some(false) || ignore.is_none(){
                 alert().await;
@@ -197,11 +255,35 @@ async fn main() -> Result<()> {
             };
             cprint!(Color::Green, "Successfully created `{}`", name);
         }
-        Cli::Update => {
-            // updates to the latest repo
-            repo_update().await?;
+        Action::Update { rollback, keep_going } => {
+            if rollback {
+                // restores the previous repo
+                repo_rollback().await?;
+            } else if !initialized {
+                // updates to the latest repo
+                repo_update(keep_going).await?;
+            }
+        }
+        Action::Verify => {
+            // recheck every template in the local store
+            let failed = dir()?.verify().await?;
+            if failed > 0 {
+                return Err(Error::VerificationFailed(failed));
+            }
+            cprint!(Color::Green, "All templates passed verification!");
+        }
+        Action::Doctor => {
+            let problems = doctor::doctor().await?;
+            if problems > 0 {
+                return Err(Error::DoctorFailed(problems));
+            }
+            cprint!(Color::Green, "No problems found!");
         }
-        Cli::Upgrade => {
+        Action::Upgrade => {
+            // both TexCreate and the templates are downloaded
+            if settings::is_offline() {
+                return Err(Error::Offline);
+            }
             cprint!(Color::Yellow, "Updating TexCreate...");
             // update TexCreate
             let _ = Command::new("cargo")
@@ -212,54 +294,63 @@ async fn main() -> Result<()> {
             cprint!(Color::Green, "Successfully updated TexCreate!");
             cprint!(Color::Yellow, "Checking for new templates...");
             // if there is an available update for the template, then we will update
-            // if not then we are done
-            if update_alert().await.is_some(){
-                repo_update().await?;
+            // if not then we are done, the latest version is checked now instead of using the cached one
+            let current = dir()?.read_repo().await?.version();
+            if get_latest_num().await? > current {
+                repo_update(false).await?;
             }
             cprint!(Color::Green, "Done!");
         }
-        Cli::List { repo } => match repo {
+        Action::List { repo, json } => match repo {
             // the default is to list out mkproj templates
             None => mkproj_repo_list().await?,
-            // if the repo is custom we will list the custom templates
-            // if not we will do mkproj templates
+            // if the repo is custom or a store we will list its templates
             Some(repo) => match repo.as_str() {
-                "custom" => {
-                    let dir = Dir::new();
-                    dir.read_custom_repo().await?;
-                }
-                _ => mkproj_repo_list().await?,
+                "mkproj" => mkproj_repo_list().await?,
+                _ => templates_display(&repo, json).await?,
             },
         },
-        Cli::Compile => {
+        Action::Show { template, repo } => {
+            // the default is to look in the mkproj repo
+            let repo = repo.unwrap_or_else(|| "mkproj".to_string());
+            show_template(&template, &repo).await?;
+        }
+        Action::Search { query } => search::search(&query).await?,
+        Action::Template(c) => {
+            // run the given template command
+            c.run_command().await?;
+        }
+        Action::Compile => {
             // get the compiler from the config file
             let compiler = Compiler::from_file().await?;
             // compile the project using the appropriate compiler
             compiler.compile().await?;
         }
-        Cli::Texcgen(c) => {
+        Action::Texcgen(c) => {
             // run the given texcgen command
             c.run_command().await;
         }
-        Cli::Open => {
+        Action::Open => {
             // open the homepage for TexCreate
             open::that("https://texcreate.mkproj.com")?;
         }
-        Cli::Web => {
+        Action::Web => {
             // launch the web application for TexCreate
             let _ = web().launch().await.unwrap();
             // remove the html file when it closes
             remove_file("index.html").await?;
         }
-        Cli::GenComplete{shell} => {
+        Action::GenComplete{shell} => {
             auto_complete(shell)?
         }
-        Cli::Feedback{subject} => {
+        Action::Feedback{subject} => {
             let url = format!("mailto:texcreate_feedback@mkproj.com?subject={subject}");
             open::that(url)?
         }
-        Cli::Donate => open::that("https://www.buymeacoffee.com/mustafif09Q")?
+        Action::Donate => open::that("https://www.buymeacoffee.com/mustafif09Q")?
     }
+    // the command is done, so the background update check can be given a moment to save what it found
+    finish_update_check().await;
     Ok(())
 }
 
@@ -269,6 +360,85 @@ async fn mkproj_repo_list() -> Result<()> {
     repo_display().await?;
     Ok(())
 }
+// removes everything texcreate owns in its home, asking first unless `yes` is given
+async fn reset_home(yes: bool) -> Result<()> {
+    let dir = dir()?;
+    // the repo couldn't be downloaded again, which would leave an empty home
+    if settings::is_offline() {
+        return Err(Error::Offline);
+    }
+    if !yes {
+        cprint!(
+            Color::Yellow,
+            "This removes the templates, including custom templates, in {}, continue? [y/N]: ",
+            dir.main_dir.display()
+        );
+        let mut input = String::new();
+        // nothing can be read when there is no terminal, which is treated as no
+        stdin().read_line(&mut input)?;
+        if !matches!(input.trim().to_lowercase().as_str(), "y" | "yes") {
+            return Err(Error::Cancelled);
+        }
+    }
+    cprint!(Color::Yellow, "Removing the templates in: {}", dir.main_dir.display());
+    dir.reset().await
+}
+// initializes the texcreate directory and gets the latest repo, anything that already exists is kept
+async fn init() -> Result<()> {
+    let dir = dir()?;
+    // put back a repo left behind by a failed update or rollback, before an empty `mkproj` is created
+    if dir.staging.exists() {
+        dir.recover_staging().await?;
+    }
+    // creates the layout, or what is missing of it
+    cprint!(
+        Color::Magenta,
+        "Creating TeXCreate directory layout at: {}",
+        dir.main_dir.display()
+    );
+    dir.build().await?;
+    // gets the latest repo for mkproj, unless it is already installed
+    if dir.repo_installed().await {
+        let repo = dir.read_repo().await?;
+        cprint!(
+            Color::Green,
+            "Repo v{} is already installed, use `texcreate update` to get the latest repo.",
+            repo.version()
+        );
+    } else {
+        repo_update(false).await?;
+    }
+    Ok(())
+}
+// checks that texcreate is initialized, offering to initialize it if it isn't
+// returns whether it was initialized now
+async fn ensure_init() -> Result<bool> {
+    let dir = dir()?;
+    // a failed update or rollback may have left `mkproj` in the staging directory
+    if dir.staging.exists() {
+        dir.recover_staging().await?;
+    }
+    if dir.is_initialized() {
+        return Ok(false);
+    }
+    // without a terminal to answer (for example in CI) it is initialized without asking
+    if stdin().is_terminal() {
+        cprint!(
+            Color::Yellow,
+            "TexCreate isn't initialized at {}, initialize it now? [Y/n]: ",
+            dir.main_dir.display()
+        );
+        let mut input = String::new();
+        stdin().read_line(&mut input)?;
+        if !matches!(input.trim().to_lowercase().as_str(), "" | "y" | "yes") {
+            return Err(Error::NotInitialized);
+        }
+    } else {
+        cprint!(Color::Yellow, "TexCreate isn't initialized at {}, initializing it...", dir.main_dir.display());
+    }
+    init().await?;
+    Ok(true)
+}
 // a helper function to alert a new template
 async fn alert() {
     if let Some(msg) = update_alert().await {
@@ -0,0 +1,217 @@
+use crate::cprint;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::stdin;
+use termcolor::Color::{Cyan, Red};
+use toml::Value;
+
+/// A parameter declared by a template that is prompted for when creating a new config
+///
+/// Parameters are declared in the template's JSON file under `parameters`:
+///
+/// ```json
+/// "parameters": [
+///     {
+///         "name": "course",
+///         "type": "string",
+///         "description": "The course code",
+///         "default": "MATH 101",
+///         "allowed": []
+///     }
+/// ]
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Parameter {
+    // the name of the parameter, used as its key in `texcreate.toml`
+    name: String,
+    // the type of value the parameter accepts
+    #[serde(rename = "type", default)]
+    type_: ParameterType,
+    // describes the parameter to the user
+    #[serde(default)]
+    description: String,
+    // the value used when the user doesn't enter anything
+    #[serde(default)]
+    default: Option<String>,
+    // the only values the parameter may have, empty if any value is allowed
+    #[serde(default)]
+    allowed: Vec<String>,
+}
+
+/// The types of values a `Parameter` accepts
+#[derive(Debug, Copy, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParameterType {
+    #[default]
+    String,
+    Integer,
+    Float,
+    Boolean,
+}
+
+// Only used to read the parameters out of a template's JSON file
+#[derive(Deserialize)]
+struct TemplateParameters {
+    #[serde(default)]
+    parameters: Vec<Parameter>,
+}
+
+/// Returns the parameters declared in a template's JSON string
+pub fn from_template_json(s: &str) -> Result<Vec<Parameter>> {
+    let params: TemplateParameters =
+        serde_json::from_str(s).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    Ok(params.parameters)
+}
+
+impl Parameter {
+    /// Returns the name of the parameter
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Validates a user's input and converts it into a TOML value
+    pub fn parse(&self, input: &str) -> Result<Value> {
+        let input = input.trim();
+        // an empty input uses the default value if there is one
+        let input = match (input.is_empty(), &self.default) {
+            (true, Some(default)) => default.as_str(),
+            _ => input,
+        };
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|a| a == input) {
+            return Err(Error::InvalidInput(input.to_string()));
+        }
+        let invalid = || Error::InvalidInput(input.to_string());
+        let value = match self.type_ {
+            ParameterType::String if input.is_empty() => return Err(invalid()),
+            ParameterType::String => Value::String(input.to_string()),
+            ParameterType::Integer => Value::Integer(input.parse().map_err(|_| invalid())?),
+            ParameterType::Float => Value::Float(input.parse().map_err(|_| invalid())?),
+            ParameterType::Boolean => match input.to_lowercase().as_str() {
+                "true" | "yes" => Value::Boolean(true),
+                "false" | "no" => Value::Boolean(false),
+                _ => return Err(invalid()),
+            },
+        };
+        Ok(value)
+    }
+    /// Prompts the user for the parameter's value until a valid one is entered
+    pub fn prompt_user(&self) -> Result<Value> {
+        // build the prompt, for example: `Enter course (The course code) [default: MATH 101]:`
+        let mut prompt = format!("Enter {}", &self.name);
+        if !self.description.is_empty() {
+            prompt.push_str(&format!(" ({})", &self.description));
+        }
+        if !self.allowed.is_empty() {
+            prompt.push_str(&format!(" [{}]", self.allowed.join("/")));
+        }
+        if let Some(default) = &self.default {
+            prompt.push_str(&format!(" [default: {default}]"));
+        }
+        loop {
+            let mut input = String::new();
+            cprint!(Cyan, "{prompt}: ");
+            let read = stdin().read_line(&mut input)?;
+            match self.parse(&input) {
+                Ok(value) => return Ok(value),
+                // stdin is closed, so prompting again would never end
+                Err(_) if read == 0 => return Err(Error::MissingParameter(self.name.to_string())),
+                Err(e) => cprint!(Red, "{e}"),
+            }
+        }
+    }
+}
+
+/// Returns the value of every parameter a template declares
+///
+/// Values from the config are used first, then the parameter's default. A parameter with neither
+/// gives an error, since its placeholder would otherwise be left in the document.
+pub fn resolve(params: &[Parameter], values: &BTreeMap<String, Value>) -> Result<BTreeMap<String, Value>> {
+    // keep the config's values, even ones the template no longer declares
+    let mut resolved = values.clone();
+    for param in params {
+        if resolved.contains_key(&param.name) {
+            continue;
+        }
+        // an empty input is the default, and is invalid without one
+        let value = param
+            .parse("")
+            .map_err(|_| Error::MissingParameter(param.name.to_string()))?;
+        resolved.insert(param.name.to_string(), value);
+    }
+    Ok(resolved)
+}
+
+/// Returns the text used to replace a parameter's placeholder in a template
+pub fn value_to_string(value: &Value) -> String {
+    match value {
+        // strings would otherwise be quoted
+        Value::String(s) => s.to_string(),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(json: &str) -> Parameter {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn parse_converts_to_the_declared_type() {
+        let p = param(r#"{"name": "n", "type": "integer"}"#);
+        assert_eq!(p.parse(" 42 ").unwrap(), Value::Integer(42));
+        assert!(p.parse("forty").is_err());
+        let p = param(r#"{"name": "b", "type": "boolean"}"#);
+        assert_eq!(p.parse("Yes").unwrap(), Value::Boolean(true));
+        assert_eq!(p.parse("false").unwrap(), Value::Boolean(false));
+    }
+
+    #[test]
+    fn parse_uses_the_default_and_allowed_values() {
+        let p = param(r#"{"name": "term", "default": "Fall", "allowed": ["Fall", "Winter"]}"#);
+        assert_eq!(p.parse("").unwrap(), Value::String("Fall".to_string()));
+        assert_eq!(p.parse("Winter").unwrap(), Value::String("Winter".to_string()));
+        assert!(p.parse("Summer").is_err());
+        // a string without a default can't be empty
+        assert!(param(r#"{"name": "course"}"#).parse("  ").is_err());
+    }
+
+    #[test]
+    fn from_template_json_reads_parameters() {
+        let params = from_template_json(r#"{"name": "t", "parameters": [{"name": "course"}]}"#).unwrap();
+        assert_eq!(params.len(), 1);
+        assert_eq!(params[0].name(), "course");
+        assert!(from_template_json(r#"{"name": "t"}"#).unwrap().is_empty());
+        assert!(from_template_json("{").is_err());
+    }
+
+    #[test]
+    fn resolve_fills_defaults_and_keeps_config_values() {
+        let params = vec![
+            param(r#"{"name": "course", "default": "MATH 101"}"#),
+            param(r#"{"name": "year", "type": "integer"}"#),
+        ];
+        let mut values = BTreeMap::new();
+        values.insert("year".to_string(), Value::Integer(2024));
+        let resolved = resolve(&params, &values).unwrap();
+        assert_eq!(resolved["course"], Value::String("MATH 101".to_string()));
+        assert_eq!(resolved["year"], Value::Integer(2024));
+    }
+
+    #[test]
+    fn resolve_names_a_missing_parameter() {
+        let params = vec![param(r#"{"name": "course"}"#)];
+        match resolve(&params, &BTreeMap::new()) {
+            Err(Error::MissingParameter(name)) => assert_eq!(name, "course"),
+            other => panic!("expected a missing parameter, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn value_to_string_leaves_strings_unquoted() {
+        assert_eq!(value_to_string(&Value::String("a b".to_string())), "a b");
+        assert_eq!(value_to_string(&Value::Integer(3)), "3");
+    }
+}
@@ -1,70 +1,202 @@
+use std::collections::BTreeMap;
 use std::str::FromStr;
-use std::time::Duration;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use crate::cprint;
-use crate::dir::DIR;
+use crate::dir::{dir, valid_name};
+use crate::checksum::{read_checksums, sha256, verify_template, write_checksums};
 use crate::error::*;
-use reqwest::Client;
+use crate::settings::{self, is_offline, settings, LATEST_URL_VAR, REPO_URL_VAR, TEMPLATE_URL_VAR};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use lazy_static::lazy_static;
+use reqwest::{Client, Response, Url};
 use termcolor::Color;
-use texcore::template::Version;
+use toml::{Table, Value};
+use texcore::template::{Template, Version};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{read, read_to_string, remove_file, write, File};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
 use texcreate_repo::Repo;
 use tokio::spawn;
 use crate::error::Error::IncompatibleVersion;
 
-// The web address of texcreate to send requests to
-const ADDRESS: &str = "https://texcreate.mkproj.com";
-/// Returns the github link to download a template file given a version number and template name
-pub fn gh_link(num: u64, name: &str) -> String {
-    format!("https://github.com/MKProj/mkproj_texcgen/releases/download/v{num}/{name}.json")
+// The web address of texcreate to get the latest repo version from
+const LATEST_URL: &str = "https://texcreate.mkproj.com/repo/latest";
+// The github release URL patterns, where `{num}` is the repo version and `{name}` the template
+const TEMPLATE_URL: &str = "https://github.com/MKProj/mkproj_texcgen/releases/download/v{num}/{name}.json";
+const REPO_URL: &str = "https://github.com/MKProj/mkproj_texcgen/releases/download/v{num}/repo.toml";
+
+/// Returns the link to get the latest repo version number
+pub fn latest_link() -> String {
+    settings::url(LATEST_URL_VAR, &settings().latest_url, LATEST_URL)
+}
+/// Returns the link to download a template file given a version number and template name
+pub fn template_link(num: u64, name: &str) -> String {
+    settings::url(TEMPLATE_URL_VAR, &settings().template_url, TEMPLATE_URL)
+        .replace("{num}", &num.to_string())
+        .replace("{name}", name)
 }
-/// Returns the repo github link to download `repo.toml` given a version number
+/// Returns the link to download `repo.toml` given a version number
 pub fn repo_link(num: u64) -> String {
-    format!("https://github.com/MKProj/mkproj_texcgen/releases/download/v{num}/repo.toml")
+    settings::url(REPO_URL_VAR, &settings().repo_url, REPO_URL).replace("{num}", &num.to_string())
 }
 
-/// Sends a request to get the latest mkproj template repo version number
-pub async fn get_latest_num() -> Result<u64> {
-    let client = Client::new();
-    let link = format!("{ADDRESS}/repo/latest");
-    let resp = client.get(&link).timeout(Duration::from_secs(10)).send().await.or(Err(Error::Timeout));
-    let b = resp?.bytes().await.unwrap();
-    let s = String::from_utf8(b.to_vec()).unwrap();
-    let num = s.trim().parse::<u64>().unwrap();
-    Ok(num)
+// The number of times a request is retried after a transient failure
+const RETRIES: u32 = 3;
+// The wait before the first retry, which doubles after each retry
+const BACKOFF: Duration = Duration::from_millis(500);
+
+// The most templates downloaded at once, whatever the `concurrent_downloads` setting is
+const MAX_DOWNLOADS: usize = 32;
+// The time a template has to download in
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Runs a request, retrying transient failures with exponential backoff
+async fn retry<T, F, Fut>(mut request: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut wait = BACKOFF;
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Err(e) if attempt < RETRIES && is_transient(&e) => {
+                attempt += 1;
+                sleep(wait).await;
+                wait *= 2;
+            }
+            result => return result,
+        }
+    }
 }
-/// Returns a vector of bytes of a template given a version number and template name
-async fn get_template_data(num: u64, name: &str) -> Vec<u8> {
-    let link = gh_link(num, name);
+
+// Gets the data at a link, retrying transient failures
+async fn fetch(link: &str, time: Duration) -> Result<Vec<u8>> {
+    retry(|| fetch_once(link, time)).await
+}
+
+// Gets the data at a link once, which can be a `file://` URL to use a mirror on the local file system
+async fn fetch_once(link: &str, time: Duration) -> Result<Vec<u8>> {
+    // a mirror on the local file system doesn't need the network, so it works while offline
+    if link.starts_with("file://") {
+        let path = Url::parse(link)
+            .ok()
+            .and_then(|u| u.to_file_path().ok())
+            .ok_or(Error::InvalidPath(link.to_string()))?;
+        return read(&path).await.or(Err(Error::InvalidPath(link.to_string())));
+    }
+    if is_offline() {
+        return Err(Error::Offline);
+    }
+    let resp = request(link, time).await?;
+    let bytes = resp.bytes().await.or(Err(Error::BadBody(link.to_string())))?;
+    Ok(bytes.to_vec())
+}
+
+// Sends a request, making sure it's answered with a success
+async fn request(link: &str, time: Duration) -> Result<Response> {
     let client = Client::new();
-    let resp = client.get(&link).send().await.unwrap();
-    let bytes = resp.bytes().await.unwrap();
-    bytes.to_vec()
+    let resp = client.get(link).timeout(time).send().await.or(Err(Error::Timeout))?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(Error::HttpStatus(link.to_string(), status.as_u16()));
+    }
+    Ok(resp)
 }
 
-// gets repo link
-async fn get_repo_link() -> Result<String>{
-    let num = get_latest_num().await?;
-    Ok(repo_link(num))
+// Downloads a template straight to a file, showing the progress on its bar
+async fn download_template(num: u64, name: &str, path: &Path, pb: &ProgressBar) -> Result<()> {
+    let link = template_link(num, name);
+    retry(|| download(&link, path, pb))
+        .await
+        .map_err(|e| Error::Download(name.to_string(), Box::new(e)))
 }
 
-// Gets the latest repo and saves it
-async fn get_latest_repo() -> Result<()> {
-    let repo_link = get_repo_link().await?;
-    DIR.save_repo(&repo_link).await?;
+// Streams the data at a link to a file once, so the data is never kept in memory
+async fn download(link: &str, path: &Path, pb: &ProgressBar) -> Result<()> {
+    // a retry starts over with an empty file
+    pb.set_position(0);
+    let mut file = File::create(path).await?;
+    // a mirror on the local file system is already on disk, so it's simply copied
+    if link.starts_with("file://") {
+        let data = fetch_once(link, DOWNLOAD_TIMEOUT).await?;
+        pb.set_length(data.len() as u64);
+        file.write_all(&data).await?;
+        pb.inc(data.len() as u64);
+        return Ok(());
+    }
+    if is_offline() {
+        return Err(Error::Offline);
+    }
+    let mut resp = request(link, DOWNLOAD_TIMEOUT).await?;
+    if let Some(len) = resp.content_length() {
+        pb.set_length(len)
+    }
+    // write each chunk as it arrives
+    while let Some(chunk) = resp.chunk().await.or(Err(Error::BadBody(link.to_string())))? {
+        file.write_all(&chunk).await?;
+        pb.inc(chunk.len() as u64);
+    }
+    file.flush().await?;
     Ok(())
 }
 
-// the latest repo
-async fn latest_repo() -> Result<Repo>{
-    let repo_link = get_repo_link().await?;
-    Ok(Repo::get_repo(&repo_link).await)
+// Checks if a failed request may succeed if it is tried again
+fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::Timeout | Error::BadBody(_) => true,
+        // the server is having problems or is asking us to slow down
+        Error::HttpStatus(_, status) => *status >= 500 || *status == 429,
+        _ => false,
+    }
+}
+
+/// Sends a request to get the latest mkproj template repo version number
+pub async fn get_latest_num() -> Result<u64> {
+    let link = latest_link();
+    let b = fetch(&link, Duration::from_secs(10)).await?;
+    let s = String::from_utf8_lossy(&b);
+    let num = s.trim().parse::<u64>().or(Err(Error::ParseFailed(link)))?;
+    Ok(num)
+}
+/// Returns a vector of bytes of a template given a version number and template name
+async fn get_template_data(num: u64, name: &str) -> Result<Vec<u8>> {
+    fetch(&template_link(num, name), DOWNLOAD_TIMEOUT)
+        .await
+        .map_err(|e| Error::Download(name.to_string(), Box::new(e)))
+}
+
+/// Returns the text of `repo.toml` given a version number
+async fn get_repo_text(num: u64) -> Result<String> {
+    let link = repo_link(num);
+    let bytes = fetch(&link, Duration::from_secs(60)).await?;
+    String::from_utf8(bytes).or(Err(Error::ParseFailed(link)))
 }
 
 /// Updates the mkprojects directory to the latest release
-pub async fn repo_update() -> Result<()> {
+///
+/// If `keep_going` is set, templates that fail to download are skipped and the rest are saved,
+/// otherwise the update is aborted and the current repo is left untouched.
+pub async fn repo_update(keep_going: bool) -> Result<()> {
+    // get the latest version number and its `repo.toml`
+    let num = get_latest_num().await?;
+    let repo_text = get_repo_text(num).await?;
+    // make sure we got a valid `repo.toml` before using it
+    let repo: Repo = toml::from_str(&repo_text).map_err(|_| Error::ParseFailed(repo_link(num)))?;
+    // every template is saved by its name, so a single name that would leave the staging directory
+    // rejects the whole repo before anything is written
+    if let Some(name) = repo.info().keys().find(|name| !valid_name(name)) {
+        return Err(Error::InvalidTemplate(name.to_string()));
+    }
     // check if repo exists...
-    if DIR.repo_exists(){
+    if dir()?.repo_exists(){
         // check latest repo
-        let repo = latest_repo().await?;
         let min_version = repo.texc_vers();
         let v = env!("CARGO_PKG_VERSION");
         let current_vers = Version::from_str(v.trim()).unwrap();
@@ -73,40 +205,176 @@ pub async fn repo_update() -> Result<()> {
             return Ok(());
         }
     }
-    // gets the latest repo in a separate thread
-    spawn(get_latest_repo() )
-        .await
-        .unwrap()?;
-    // clear mkproj directory
-    DIR.clear().await?;
-    // read the repo so we can get all of the templates name to download
-    let repo = DIR.read_repo().await?;
-    // get the latest version number
-    let num = get_latest_num().await?;
-    // stores a tuple of `(template name, template bytes join handle)`
-    let mut tasks = Vec::new();
-    // iterate through `repo` for all template names in the release
+    // the SHA-256 of each template if the release provides them
+    let checksums = read_checksums(&repo_text);
+    if checksums.is_empty() {
+        cprint!(Color::Yellow, "Repo v{num} has no checksums, templates will only be checked to be valid...");
+    }
+    // the templates are downloaded straight to a staging directory, so the current repo is untouched
+    // until every template is saved and it can be swapped in
+    let dir = dir()?;
+    dir.begin_staging().await?;
+    let staged: Result<Vec<String>> = async {
+        // download and verify every template
+        let results = download_templates(num, &repo, &checksums).await;
+        // the SHA-256 of each downloaded template, recorded in our `repo.toml`
+        let mut recorded = BTreeMap::new();
+        // the templates skipped with `keep_going`
+        let mut failed = Vec::new();
+        for (name, result) in results {
+            match result {
+                Ok(sha) => {
+                    recorded.insert(name, sha);
+                }
+                Err(e) if keep_going => {
+                    cprint!(Color::Yellow, "Skipping `{name}`: {e}");
+                    failed.push(name)
+                }
+                // a bad download never reaches `mkproj`
+                Err(e) => return Err(e),
+            }
+        }
+        // there is nothing to update to if every template was skipped
+        if recorded.is_empty() && !failed.is_empty() {
+            return Err(Error::DownloadsFailed(failed.len()));
+        }
+        // remove what was left of the skipped templates
+        for name in &failed {
+            let path = dir.staged_path(name)?;
+            if path.exists() {
+                remove_file(path).await?;
+            }
+        }
+        // `repo.toml` with the checksums so `texcreate verify` can recheck the templates, and without
+        // the skipped templates so nothing expects them to be installed
+        let repo_text = without_templates(&write_checksums(&repo_text, &recorded)?, &failed)?;
+        // swap in the new repo, keeping the current one for `texcreate update --rollback`
+        dir.commit_staging(&repo_text).await?;
+        Ok(failed)
+    }
+    .await;
+    let failed = match staged {
+        Ok(failed) => failed,
+        Err(e) => {
+            dir.discard_staging().await?;
+            return Err(e);
+        }
+    };
+    // keep a copy of this version, so projects pinned to it can still be built after future updates
     for (name, _) in repo.into_iter() {
-        // to use the name after, we will need to create a new owner n that we will use when pushing
+        let path = dir.mkproj.join(format!("{name}.json"));
+        if !failed.contains(&name) {
+            dir.save_version(num, &name, &read(&path).await?).await?;
+        }
+    }
+    // we know this is the latest version, so there is no need to check again for a while
+    let _ = State::save(num).await;
+    if !failed.is_empty() {
+        cprint!(Color::Yellow, "Updated to repo v{num} without {} template(s)", failed.len());
+        return Err(Error::DownloadsFailed(failed.len()));
+    }
+    cprint!(Color::Green, "Successfully updated to repo v{num}!");
+    Ok(())
+}
+// Returns a `repo.toml` string without some of its templates
+fn without_templates(repo: &str, names: &[String]) -> Result<String> {
+    let mut table: Table = toml::from_str(repo).map_err(|_| Error::InvalidInput("repo.toml".to_string()))?;
+    if let Some(Value::Table(info)) = table.get_mut("info") {
+        for name in names {
+            info.remove(name);
+        }
+        // `num` is the number of templates, which has to match what's left
+        let num = info.len() as i64;
+        table.insert("num".to_string(), Value::Integer(num));
+    }
+    Ok(toml::to_string(&table).unwrap())
+}
+
+// Downloads every template of a repo to the staging directory, returning each template's SHA-256
+//
+// Each template has its own progress bar under a bar for the whole update, and only the
+// `concurrent_downloads` setting's number of templates are downloaded at once.
+async fn download_templates(
+    num: u64,
+    repo: &Repo,
+    checksums: &BTreeMap<String, String>,
+) -> Vec<(String, Result<String>)> {
+    let names: Vec<String> = repo.info().into_keys().collect();
+    let multi = MultiProgress::new();
+    let overall = multi.add(ProgressBar::new(names.len() as u64));
+    overall.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.green/white}] {pos}/{len} templates")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    let style = ProgressStyle::default_bar()
+        .template("{spinner:.green} [{msg}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+        .unwrap()
+        .progress_chars("#>-");
+    let permits = Arc::new(Semaphore::new(settings().concurrent_downloads.clamp(1, MAX_DOWNLOADS)));
+    // stores a tuple of `(template name, SHA-256 join handle)`
+    let mut tasks = Vec::new();
+    for name in names {
+        let (multi, overall, style, permits) = (multi.clone(), overall.clone(), style.clone(), permits.clone());
+        let checksum = checksums.get(&name).cloned();
         let n = name.clone();
-        // create a new task that gets the template data with the version number and template name
-        // each task will have type `JoinHandle<Vec<u8>>` so when we join it back to main thread we
-        // get the template's bytes
-        let task = spawn(async move { get_template_data(num, &name).await });
-        // push the name of the template and task to `tasks`
+        let task = spawn(async move {
+            // wait for a free download slot before showing a bar
+            let _permit = permits.acquire_owned().await.expect("Download semaphore closed in update!");
+            let pb = multi.insert_before(&overall, ProgressBar::new(0));
+            pb.set_style(style);
+            pb.set_message(name.clone());
+            let path = dir()?.staged_path(&name)?;
+            let result: Result<String> = async {
+                download_template(num, &name, &path, &pb).await?;
+                // check the template's checksum and that it is a valid template
+                let bytes = read(&path).await?;
+                verify_template(&name, &bytes, checksum.as_ref())?;
+                Ok(sha256(&bytes))
+            }
+            .await;
+            pb.finish_and_clear();
+            overall.inc(1);
+            result
+        });
         tasks.push((n, task))
     }
-
-    // iterate through `tasks` so we can save each template to the `mkproj` directory
+    let mut results = Vec::new();
     for (name, handle) in tasks {
-        // get the template's bytes by joining the handle
-        let bytes = handle.await.expect("Join handle failed in update!");
-        // get the file name by adding the JSON extension
-        let file_name = format!("{name}.json");
-        // save the template
-        DIR.save_mkproj(&file_name, &bytes).await?;
+        results.push((name, handle.await.expect("Join handle failed in update!")))
     }
-    cprint!(Color::Green, "Successfully updated to repo v{num}!");
+    overall.finish_and_clear();
+    results
+}
+/// Returns a template from a version of the mkprojects repo, fetching it if it isn't in the local store
+pub async fn pinned_template(name: &str, num: u64) -> Result<Template> {
+    let dir = dir()?;
+    let path = dir.version_path(name, num)?;
+    if !path.exists() {
+        // stores created before versions were kept may still have it as the current repo
+        let current = dir.read_repo().await.map(|r| r.version()).ok();
+        let mkproj_path = dir.mkproj.join(format!("{name}.json"));
+        let bytes = if current == Some(num) && mkproj_path.exists() {
+            read(&mkproj_path).await?
+        } else {
+            cprint!(Color::Yellow, "Fetching `{name}` from repo v{num}...");
+            get_template_data(num, name).await?
+        };
+        // make sure we have a template before keeping it, a missing template gives an error page
+        verify_template(name, &bytes, None)
+            .map_err(|_| Error::InvalidTemplate(format!("{name}@{num}")))?;
+        dir.save_version(num, name, &bytes).await?;
+    }
+    let data = read(&path).await?;
+    verify_template(name, &data, None)
+}
+/// Restores the previous mkprojects repo that was replaced by an update
+pub async fn repo_rollback() -> Result<()> {
+    let dir = dir()?;
+    dir.rollback().await?;
+    let repo = dir.read_repo().await?;
+    cprint!(Color::Green, "Successfully rolled back to repo v{}!", repo.version());
     Ok(())
 }
 /// Displays all mkproject templates and their description
@@ -114,28 +382,97 @@ pub async fn repo_display() -> Result<()> {
     // to make sure that we print white, we will make stdout white
     cprint!(Color::White, "\r");
     // read `repo.toml`
-    let repo = DIR.read_repo().await?;
+    let repo = dir()?.read_repo().await?;
     // use the `display()` method to print out the repo information
     repo.display();
     Ok(())
 }
+/// The last known latest repo version, kept in `state.toml` so the repo isn't checked on every command
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct State {
+    /// The latest repo version the last time it was checked
+    latest: Option<u64>,
+    /// When the repo was last checked, in seconds since the Unix epoch
+    checked: Option<u64>,
+}
+
+impl State {
+    // Reads `state.toml`, which may not exist yet or be from an older TexCreate
+    async fn read() -> Self {
+        let Ok(dir) = dir() else { return Self::default() };
+        match read_to_string(dir.main_dir.join("state.toml")).await {
+            Ok(s) => toml::from_str(&s).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+    // Saves the latest repo version as checked now
+    async fn save(latest: u64) -> Result<()> {
+        let state = Self {
+            latest: Some(latest),
+            checked: Some(now()),
+        };
+        write(dir()?.main_dir.join("state.toml"), toml::to_string(&state).unwrap()).await?;
+        Ok(())
+    }
+}
+
+// Returns the seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+lazy_static! {
+    // The background task checking for a new repo, so it can be given time to finish before exiting
+    static ref UPDATE_CHECK: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+}
+
+/// Checks for the latest repo version in the background if the last check is older than the
+/// `update_interval` setting, saving it to `state.toml` for the next command
+pub async fn spawn_update_check() {
+    let state = State::read().await;
+    // a huge interval saturates instead of overflowing, which is the same as never checking again
+    let interval = settings().update_interval.saturating_mul(60 * 60);
+    if state.checked.is_some_and(|c| now().saturating_sub(c) < interval) || is_offline() {
+        return;
+    }
+    let handle = spawn(async {
+        if let Ok(latest) = get_latest_num().await {
+            let _ = State::save(latest).await;
+        }
+    });
+    *UPDATE_CHECK.lock().unwrap() = Some(handle);
+}
+
+/// Gives the background update check a moment to finish, after the command's work is done
+pub async fn finish_update_check() {
+    let handle = UPDATE_CHECK.lock().unwrap().take();
+    if let Some(handle) = handle {
+        let _ = timeout(Duration::from_secs(2), handle).await;
+    }
+}
+
 /// Checks to see if there is a new repo, if so a message will be returned
+///
+/// The latest version is the last known one in `state.toml`, which is refreshed in the background,
+/// so this never waits on the network.
 pub async fn update_alert() -> Option<String> {
+    // start checking for the next command, if it's time to check again
+    spawn_update_check().await;
     // to check if there is any new repo
     // we will need to know what the current version is from our `repo.toml`
-    let repo = DIR.read_repo().await;
+    let repo = dir().ok()?.read_repo().await;
     // handle the `Result` using a match statement
     match repo {
         // if we get a repo, which we should unless TexCreate isn't initialized
         Ok(r) => {
             // to know what version we have we will use the `version()` method and store it in `v`
             let v = r.version();
-            // to get the current version, we will use the `get_latest_num()` function that sends a
-            // request to get the latest release version.
-            let current = match get_latest_num().await{
-                Ok(v) => v,
-                Err(_) => return None
-            };
+            // to get the current version, we will use the last known latest version
+            // there is none until the first check has finished
+            let current = State::read().await.latest?;
             // we will check if the current version is greater than `v`
             if current > v {
                 // The goal of our message is to look like the following:
@@ -170,3 +507,17 @@ pub async fn update_alert() -> Option<String> {
         Err(_) => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn without_templates_updates_the_count() {
+        let repo = "current_vers = 2\nnum = 3\n[texcreate]\nmajor = 3\nminor = 0\npatch = 0\n[info]\nbasic = \"Basic\"\nnews = \"News\"\ngone = \"Gone\"\n";
+        let table: Table = toml::from_str(&without_templates(repo, &["gone".to_string()]).unwrap()).unwrap();
+        let info = table["info"].as_table().unwrap();
+        assert_eq!(info.keys().collect::<Vec<_>>(), ["basic", "news"]);
+        assert_eq!(table["num"].as_integer(), Some(2));
+    }
+}
@@ -0,0 +1,117 @@
+// Fuzzy matching of template names and descriptions across the template stores
+
+use crate::checksum::verify_template;
+use crate::cprint;
+use crate::dir::dir;
+use crate::error::Result;
+use strsim::levenshtein;
+use termcolor::Color;
+use tokio::fs::read;
+
+// The most suggestions to give when a template can't be found
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Returns the templates with a name similar to `name`, as `repo/name`, closest first
+pub fn suggestions(name: &str, templates: &[(String, String)]) -> Vec<String> {
+    let mut similar: Vec<(usize, String)> = templates
+        .iter()
+        .filter_map(|(repo, t)| score(name, t).map(|s| (s, format!("{repo}/{t}"))))
+        .collect();
+    similar.sort();
+    similar
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, t)| t)
+        .collect()
+}
+
+/// Prints every template whose name or description fuzzy matches a query, best match first
+pub async fn search(query: &str) -> Result<()> {
+    let dir = dir()?;
+    // the matches as `(score, repo, name, description)`
+    let mut matches = Vec::new();
+    for (repo, name) in dir.templates() {
+        // a file whose name can't be used as a template, like `a@b.json`, can't be found anyway
+        let Ok(path) = dir.template_path(&name, &repo) else {
+            continue;
+        };
+        // templates that can't be parsed are left to `texcreate verify`
+        let Ok(template) = verify_template(&name, &read(&path).await?, None) else {
+            continue;
+        };
+        // a description matches if any of its words is close to the query
+        let desc_score = template
+            .description
+            .split_whitespace()
+            .filter_map(|word| score(query, word.trim_matches(|c: char| !c.is_alphanumeric())))
+            .min();
+        let best = match (score(query, &name), desc_score) {
+            (Some(a), Some(b)) => a.min(b + 1),
+            (Some(a), None) => a,
+            // a match on the description ranks behind the same match on the name
+            (None, Some(b)) => b + 1,
+            (None, None) => continue,
+        };
+        matches.push((best, repo, name, template.description));
+    }
+    if matches.is_empty() {
+        cprint!(Color::Yellow, "No templates match `{query}`.");
+        return Ok(());
+    }
+    matches.sort();
+    for (_, repo, name, desc) in matches {
+        cprint!(Color::Green, "[{repo}] {name}: {desc}");
+    }
+    Ok(())
+}
+
+// Returns how closely `candidate` matches `query` (lower is better), or `None` if it isn't close enough
+fn score(query: &str, candidate: &str) -> Option<usize> {
+    let query = query.to_lowercase();
+    let candidate = candidate.to_lowercase();
+    if candidate.is_empty() {
+        return None;
+    }
+    if candidate == query {
+        return Some(0);
+    }
+    // a prefix or substring, like `dict` for `dictionary`, is a good match
+    if candidate.contains(&query) {
+        return Some(1);
+    }
+    // allow roughly one typo for every three characters
+    let distance = levenshtein(&query, &candidate);
+    let allowed = (query.chars().count().max(candidate.chars().count()) / 3).max(1);
+    (distance <= allowed).then_some(distance + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn score_prefers_exact_then_substring_then_typos() {
+        assert_eq!(score("Basic", "basic"), Some(0));
+        assert_eq!(score("dict", "dictionary"), Some(1));
+        assert_eq!(score("bsic", "basic"), Some(2));
+        assert_eq!(score("news", "lachaise"), None);
+        assert_eq!(score("basic", ""), None);
+    }
+
+    #[test]
+    fn suggestions_are_closest_first() {
+        let templates = vec![
+            ("mkproj".to_string(), "basic".to_string()),
+            ("custom".to_string(), "basics".to_string()),
+            ("mkproj".to_string(), "news".to_string()),
+        ];
+        assert_eq!(suggestions("basic", &templates), ["mkproj/basic", "custom/basics"]);
+        assert!(suggestions("zzzzzz", &templates).is_empty());
+    }
+
+    #[test]
+    fn not_found_error_suggests_templates() {
+        let e = crate::error::Error::TemplateNotFound("bsic".to_string(), vec!["mkproj/basic".to_string()]);
+        assert_eq!(e.to_string(), "template `bsic` not found, did you mean `mkproj/basic`?");
+    }
+}
@@ -0,0 +1,108 @@
+// The persistent settings of TexCreate, kept in `settings.toml` in the TexCreate home
+
+use crate::cprint;
+use crate::dir::dir;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::env::var;
+use std::fs::read_to_string;
+use std::sync::atomic::{AtomicBool, Ordering};
+use termcolor::Color;
+
+/// The environment variables that take priority over the URL settings
+pub const LATEST_URL_VAR: &str = "TEXCREATE_LATEST_URL";
+pub const TEMPLATE_URL_VAR: &str = "TEXCREATE_TEMPLATE_URL";
+pub const REPO_URL_VAR: &str = "TEXCREATE_REPO_URL";
+
+// Set by the global `--offline` flag
+static OFFLINE_FLAG: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    // The settings are read once, the first time they are used
+    static ref SETTINGS: Settings = Settings::read();
+}
+
+/// The settings in `settings.toml`, every setting is optional
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Settings {
+    /// Disables every network request to the template repo
+    #[serde(default)]
+    pub offline: bool,
+    /// The hours to wait before checking for a new repo again, `0` checks every time
+    #[serde(default = "default_update_interval")]
+    pub update_interval: u64,
+    /// The most templates downloaded at once during an update
+    #[serde(default = "default_concurrent_downloads")]
+    pub concurrent_downloads: usize,
+    /// The URL to get the latest repo version from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latest_url: Option<String>,
+    /// The URL pattern to download a template, where `{num}` is the repo version and `{name}` the template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_url: Option<String>,
+    /// The URL pattern to download `repo.toml`, where `{num}` is the repo version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo_url: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            offline: false,
+            update_interval: default_update_interval(),
+            concurrent_downloads: default_concurrent_downloads(),
+            latest_url: None,
+            template_url: None,
+            repo_url: None,
+        }
+    }
+}
+
+// Checks for a new repo once a day by default
+fn default_update_interval() -> u64 {
+    24
+}
+
+// Downloads a few templates at once without flooding the connection
+fn default_concurrent_downloads() -> usize {
+    4
+}
+
+impl Settings {
+    // Reads `settings.toml`, using the default settings if it doesn't exist or can't be parsed
+    fn read() -> Self {
+        let Ok(dir) = dir() else { return Self::default() };
+        let path = dir.main_dir.join("settings.toml");
+        let Ok(s) = read_to_string(&path) else { return Self::default() };
+        match toml::from_str(&s) {
+            Ok(settings) => settings,
+            Err(e) => {
+                cprint!(Color::Yellow, "Ignoring `{}`, it couldn't be parsed: {e}", path.display());
+                Self::default()
+            }
+        }
+    }
+}
+
+/// Returns the global settings
+pub fn settings() -> &'static Settings {
+    &SETTINGS
+}
+
+/// Returns a URL from its environment variable, its setting, or the default, in that order
+pub fn url(var_name: &str, setting: &Option<String>, default: &str) -> String {
+    match var(var_name) {
+        Ok(v) if !v.is_empty() => v,
+        _ => setting.clone().unwrap_or_else(|| default.to_string()),
+    }
+}
+
+/// Makes TexCreate offline for this run, used by the global `--offline` flag
+pub fn set_offline() {
+    OFFLINE_FLAG.store(true, Ordering::Relaxed)
+}
+
+/// Checks if TexCreate is offline, using the `--offline` flag or the `offline` setting
+pub fn is_offline() -> bool {
+    OFFLINE_FLAG.load(Ordering::Relaxed) || settings().offline
+}
@@ -0,0 +1,289 @@
+// Template stores installed from git repositories
+//
+// The templates of each store are saved in `main_dir/stores/<store>`, while the git repository is kept
+// in `cache/git/<store>` and where each store comes from is recorded in `main_dir/stores.toml`.
+
+use crate::checksum::verify_template;
+use crate::cprint;
+use crate::dir::{dir, valid_name};
+use crate::error::{Error, Result};
+use crate::settings::is_offline;
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use termcolor::Color;
+use tokio::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+use tokio::task::spawn_blocking;
+
+/// The prefix of a template source that is a git repository
+pub const GIT_PREFIX: &str = "git+";
+
+/// Where the templates of a store come from
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Source {
+    /// The URL of the git repository
+    pub url: String,
+    /// The branch, tag or commit to install, the remote's default branch is used if there is none
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// The commit the templates were installed from
+    pub commit: String,
+}
+
+/// Parses a `git+<url>[#ref]` source into its URL and ref
+pub fn parse_source(source: &str) -> Option<(String, Option<String>)> {
+    let rest = source.strip_prefix(GIT_PREFIX)?;
+    let (url, reference) = match rest.split_once('#') {
+        Some((url, r)) if !r.is_empty() => (url, Some(r.to_string())),
+        Some((url, _)) => (url, None),
+        None => (rest, None),
+    };
+    (!url.is_empty()).then(|| (url.to_string(), reference))
+}
+
+/// Installs the templates of a git repository into a store, returning the store's name
+///
+/// The store's name defaults to the last part of the URL without `.git`.
+pub async fn install(source: &str, store: Option<&str>, force: bool) -> Result<String> {
+    let (url, reference) =
+        parse_source(source).ok_or_else(|| Error::InvalidInput(source.to_string()))?;
+    let store = match store {
+        Some(s) => s.to_string(),
+        None => default_name(&url).ok_or_else(|| Error::InvalidInput(source.to_string()))?,
+    };
+    dir()?.check_store_name(&store)?;
+    let mut sources = read_sources().await?;
+    if sources.contains_key(&store) && !force {
+        return Err(Error::AlreadyExists(store));
+    }
+    let commit = match fetch(&store, &url, reference.as_deref()).await {
+        Ok(c) => c,
+        Err(e) => {
+            // don't keep the repository of a store that was never installed
+            let path = dir()?.cache.join("git").join(&store);
+            if !sources.contains_key(&store) && path.exists() {
+                remove_dir_all(path).await?;
+            }
+            return Err(e);
+        }
+    };
+    let count = save_templates(&store, commit).await?;
+    sources.insert(store.to_string(), Source { url, reference, commit: commit.to_string() });
+    write_sources(&sources).await?;
+    cprint!(Color::Green, "Installed {count} template(s) from commit {} into `{store}`!", short(commit));
+    Ok(store)
+}
+
+/// Pulls new commits for a store, or for every store if none is given
+pub async fn update(store: Option<&str>) -> Result<()> {
+    let mut sources = read_sources().await?;
+    let names: Vec<String> = match store {
+        Some(s) if sources.contains_key(s) => vec![s.to_string()],
+        Some(s) => return Err(Error::InvalidRepo(s.to_string())),
+        None => sources.keys().cloned().collect(),
+    };
+    if names.is_empty() {
+        cprint!(Color::Yellow, "There are no stores installed from git to update.");
+        return Ok(());
+    }
+    for name in names {
+        let source = sources[&name].clone();
+        let commit = fetch(&name, &source.url, source.reference.as_deref()).await?;
+        if commit.to_string() == source.commit {
+            cprint!(Color::Green, "`{name}` is up to date at commit {}", short(commit));
+            continue;
+        }
+        let count = save_templates(&name, commit).await?;
+        cprint!(
+            Color::Green,
+            "Updated `{name}` from commit {} to {} ({count} template(s))",
+            &source.commit[..7.min(source.commit.len())],
+            short(commit)
+        );
+        sources.insert(name, Source { commit: commit.to_string(), ..source });
+        write_sources(&sources).await?;
+    }
+    Ok(())
+}
+
+/// Reads where each store comes from in `stores.toml`, which may not exist yet
+pub async fn read_sources() -> Result<BTreeMap<String, Source>> {
+    let path = dir()?.main_dir.join("stores.toml");
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let s = read_to_string(&path).await?;
+    toml::from_str(&s).map_err(|_| Error::InvalidInput("stores.toml".to_string()))
+}
+
+// Writes where each store comes from to `stores.toml`
+async fn write_sources(sources: &BTreeMap<String, Source>) -> Result<()> {
+    let path = dir()?.main_dir.join("stores.toml");
+    write(path, toml::to_string(sources).unwrap()).await?;
+    Ok(())
+}
+
+// Fetches a ref of a repository into the store's cache, returning the commit it points to
+//
+// libgit2 blocks while it fetches, so it's run on a blocking thread to keep the runtime free.
+async fn fetch(store: &str, url: &str, reference: Option<&str>) -> Result<Oid> {
+    // a repository on the local file system can still be used while offline
+    if is_offline() && !url.starts_with("file://") {
+        return Err(Error::Offline);
+    }
+    let path = dir()?.cache.join("git").join(store);
+    let (url, reference) = (url.to_string(), reference.map(str::to_string));
+    spawn_blocking(move || fetch_blocking(&path, &url, reference.as_deref()))
+        .await
+        .expect("Git fetch task failed!")
+}
+
+// Fetches a ref of a repository into a bare repository
+//
+// libgit2 can't make shallow clones, so instead only the ref that is needed is fetched, unless
+// the ref is a commit which needs every branch and tag to be fetched to find it.
+fn fetch_blocking(path: &Path, url: &str, reference: Option<&str>) -> Result<Oid> {
+    let repo = match Repository::open_bare(path) {
+        Ok(r) => r,
+        Err(_) => Repository::init_bare(path)?,
+    };
+    let mut remote = repo.remote_anonymous(url)?;
+    let Some(r) = reference else {
+        // without a ref, the remote's default branch is used
+        remote.fetch(&["+HEAD:refs/texcreate/head"], None, None)?;
+        return peel(&repo, "refs/texcreate/head");
+    };
+    let branch = format!("+refs/heads/{r}:refs/remotes/origin/{r}");
+    let tag = format!("+refs/tags/{r}:refs/tags/{r}");
+    remote.fetch(&[branch.as_str(), tag.as_str()], None, None)?;
+    for name in [format!("refs/remotes/origin/{r}"), format!("refs/tags/{r}")] {
+        if let Ok(oid) = peel(&repo, &name) {
+            return Ok(oid);
+        }
+    }
+    // the ref may be a commit, which can only be found by fetching everything
+    remote.fetch(&["+refs/heads/*:refs/remotes/origin/*", "+refs/tags/*:refs/tags/*"], None, None)?;
+    let object = repo
+        .revparse_single(r)
+        .map_err(|_| Error::InvalidInput(format!("{url}#{r}")))?;
+    let commit = object.peel_to_commit()?.id();
+    Ok(commit)
+}
+
+// Returns the commit a reference points to
+fn peel(repo: &Repository, name: &str) -> Result<Oid> {
+    Ok(repo.find_reference(name)?.peel_to_commit()?.id())
+}
+
+// Saves every template found in a commit to the store, returning the number of templates
+//
+// Any JSON file that isn't a template is skipped, and the templates are saved using their file name.
+async fn save_templates(store: &str, commit: Oid) -> Result<usize> {
+    let dir = dir()?;
+    let path = dir.cache.join("git").join(store);
+    // read every template out of the repository first, so it is closed before anything is saved
+    let templates = spawn_blocking(move || read_templates(&path, commit))
+        .await
+        .expect("Git read task failed!")?;
+    // replace the store's templates, so templates removed from the repository are removed too
+    let store_path = dir.stores.join(store);
+    if store_path.exists() {
+        remove_dir_all(&store_path).await?;
+    }
+    create_dir_all(&store_path).await?;
+    for (name, data) in &templates {
+        write(store_path.join(format!("{name}.json")), data).await?;
+    }
+    Ok(templates.len())
+}
+
+// Reads every valid template out of a commit of a bare repository, keyed by the template's file name
+fn read_templates(path: &Path, commit: Oid) -> Result<BTreeMap<String, Vec<u8>>> {
+    let repo = Repository::open_bare(path)?;
+    let tree = repo.find_commit(commit)?.tree()?;
+    let mut blobs = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        let name = entry.name().unwrap_or_default();
+        if entry.kind() == Some(ObjectType::Blob) && name.ends_with(".json") {
+            blobs.push((format!("{root}{name}"), entry.id()))
+        }
+        TreeWalkResult::Ok
+    })?;
+    let mut templates: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    for (file, id) in blobs {
+        let name = file.rsplit('/').next().unwrap().trim_end_matches(".json").to_string();
+        // a name like `a@b` or `.json` couldn't be used to find the template once it's installed
+        if !valid_name(&name) {
+            cprint!(Color::Yellow, "Skipping `{file}`, `{name}` can't be used as a template name...");
+            continue;
+        }
+        let data = repo.find_blob(id)?.content().to_vec();
+        if verify_template(&name, &data, None).is_err() {
+            cprint!(Color::Yellow, "Skipping `{file}`, it isn't a valid template...");
+            continue;
+        }
+        if templates.contains_key(&name) {
+            cprint!(Color::Yellow, "Skipping `{file}`, a template named `{name}` was already found...");
+            continue;
+        }
+        templates.insert(name, data);
+    }
+    Ok(templates)
+}
+
+// Returns the last part of a URL without `.git`, for example `team-templates` for `https://host/team-templates.git`
+fn default_name(url: &str) -> Option<String> {
+    let last = url.trim_end_matches('/').rsplit(['/', ':']).next()?;
+    let name = last.trim_end_matches(".git");
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+// Returns the short form of a commit
+fn short(commit: Oid) -> String {
+    commit.to_string()[..7].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+
+    #[test]
+    fn parse_source_splits_the_reference() {
+        assert_eq!(parse_source("git+https://host/t.git"), Some(("https://host/t.git".to_string(), None)));
+        assert_eq!(
+            parse_source("git+https://host/t.git#v1"),
+            Some(("https://host/t.git".to_string(), Some("v1".to_string())))
+        );
+        assert_eq!(parse_source("git+https://host/t.git#"), Some(("https://host/t.git".to_string(), None)));
+        assert_eq!(parse_source("git+"), None);
+        assert_eq!(parse_source("https://host/t.git"), None);
+    }
+
+    #[test]
+    fn default_name_is_the_last_part_of_the_url() {
+        assert_eq!(default_name("https://host/team-templates.git/"), Some("team-templates".to_string()));
+        assert_eq!(default_name("git@host:templates.git"), Some("templates".to_string()));
+        assert_eq!(default_name("https://host/.git"), None);
+    }
+
+    #[test]
+    fn read_templates_skips_unusable_names() {
+        let path = std::env::temp_dir().join(format!("texcreate-store-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let repo = Repository::init_bare(&path).unwrap();
+        let template = r#"{"name":"good","description":"","version":{"major":1,"minor":0,"patch":0},"element_list":{"metadata":{"author":"","date":"","title":"","fontsize":11,"papersize":"letterpaper","doc_class":"article","maketitle":true},"list":[]}}"#;
+        let blob = repo.blob(template.as_bytes()).unwrap();
+        let mut tree = repo.treebuilder(None).unwrap();
+        for name in ["good.json", "a@b.json", ".json"] {
+            tree.insert(name, blob, 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let commit = repo.commit(None, &signature, &signature, "templates", &tree, &[]).unwrap();
+        let templates = read_templates(&path, commit).unwrap();
+        assert_eq!(templates.keys().collect::<Vec<_>>(), ["good"]);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,542 @@
+use crate::bundle;
+use crate::checksum::verify_template;
+use crate::cprint;
+use crate::diff::print_unified_diff;
+use crate::dir::dir;
+use crate::error::{Error, Result};
+use crate::latex::{
+    class_conflicts, command_args, package_conflicts, packages, parse_document, print_highlighted,
+    TexDocument,
+};
+use crate::repo::pinned_template;
+use crate::store;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use termcolor::Color::{Cyan, Green, Magenta, Red, White, Yellow};
+use texcore::template::Template;
+use texcore::{Any, Custom, Element, Input, Level, Metadata, Package};
+use tokio::fs::{read, read_to_string, write};
+use tokio::process::Command;
+
+/// Commands to manage the templates in the `custom` repo
+#[derive(StructOpt)]
+pub enum TemplateCommands {
+    #[structopt(about = "Adds a template JSON file to the custom repo.")]
+    Add {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        #[structopt(short, long, help = "The name to save the template as (default: file name).")]
+        name: Option<String>,
+        #[structopt(short, long, help = "Overwrite a template with the same name.")]
+        force: bool,
+    },
+    #[structopt(about = "Removes a template from the custom repo.")]
+    Remove { name: String },
+    #[structopt(about = "Renames a template in the custom repo.")]
+    Rename {
+        from: String,
+        to: String,
+        #[structopt(short, long, help = "Overwrite a template with the new name.")]
+        force: bool,
+    },
+    #[structopt(about = "Exports a template from the custom repo to a JSON file.")]
+    Export {
+        name: String,
+        #[structopt(short, long, parse(from_os_str), help = "The file to export to (default: `<name>.json`).")]
+        output: Option<PathBuf>,
+        #[structopt(short, long, help = "Overwrite the output file.")]
+        force: bool,
+    },
+    #[structopt(about = "Shows the details of a template in the custom repo.")]
+    Show { name: String },
+    #[structopt(about = "Checks a template's packages are installed and don't conflict.")]
+    Lint {
+        name: String,
+        #[structopt(short, long, help = "The repo to look in (default: custom).")]
+        repo: Option<String>,
+    },
+    #[structopt(about = "Shows the changes between two templates, given as `repo/name@version`.")]
+    Diff { a: String, b: String },
+    #[structopt(about = "Packs a custom template and its assets into a bundle.")]
+    Pack {
+        name: String,
+        #[structopt(short, long, parse(from_os_str), help = "Asset files or directories to include in the bundle.")]
+        assets: Vec<PathBuf>,
+        #[structopt(short, long, parse(from_os_str), help = "The bundle to create (default: `<name>.texbundle`).")]
+        output: Option<PathBuf>,
+        #[structopt(short, long, help = "Overwrite the output file.")]
+        force: bool,
+    },
+    #[structopt(about = "Installs a template bundle to the custom repo, or the templates of a git repo (`git+<url>[#ref]`) to a store.")]
+    Install {
+        source: String,
+        #[structopt(short, long, help = "The name to save a bundle's template as (default: the bundle's name).")]
+        name: Option<String>,
+        #[structopt(short, long, help = "The store to install a git repo to (default: the repo's name).")]
+        store: Option<String>,
+        #[structopt(short, long, help = "Overwrite a template or store with the same name.")]
+        force: bool,
+    },
+    #[structopt(about = "Pulls new commits for the stores installed from git.")]
+    Update { store: Option<String> },
+    #[structopt(about = "Creates a custom template from an existing `.tex` document.")]
+    Import {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        #[structopt(short, long, help = "The name to save the template as (default: file name).")]
+        name: Option<String>,
+        #[structopt(short, long, help = "The template's description.")]
+        description: Option<String>,
+        #[structopt(long, help = "Keep the document's body in the template.")]
+        body: bool,
+        #[structopt(long, help = "LaTeX to use as the template's body instead of the document's.")]
+        stub: Option<String>,
+        #[structopt(short, long, help = "Overwrite a template with the same name.")]
+        force: bool,
+    },
+}
+
+impl TemplateCommands {
+    /// Runs the command depending on the variant
+    pub async fn run_command(&self) -> Result<()> {
+        let dir = dir()?;
+        match self {
+            TemplateCommands::Add { file, name, force } => {
+                // the template's name defaults to the file name without `.json`
+                let name = match name {
+                    Some(n) => n.to_string(),
+                    None => file_stem(file)?,
+                };
+                let data = read(file).await?;
+                // only valid templates are allowed in the custom repo
+                verify_template(&name, &data, None)?;
+                dir.save_custom(&name, &data, *force).await?;
+                cprint!(Green, "Successfully added `{name}` to the custom repo!");
+            }
+            TemplateCommands::Remove { name } => {
+                dir.remove_custom(name).await?;
+                cprint!(Green, "Successfully removed `{name}` from the custom repo!");
+            }
+            TemplateCommands::Rename { from, to, force } => {
+                dir.rename_custom(from, to, *force).await?;
+                cprint!(Green, "Successfully renamed `{from}` to `{to}`!");
+            }
+            TemplateCommands::Export { name, output, force } => {
+                let path = dir.template_path(name, "custom")?;
+                let output = output
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from(format!("{name}.json")));
+                if output.exists() && !force {
+                    return Err(Error::AlreadyExists(output.display().to_string()));
+                }
+                let data = read(&path).await?;
+                write(&output, data).await?;
+                cprint!(Green, "Successfully exported `{name}` to `{}`", output.display());
+            }
+            TemplateCommands::Show { name } => {
+                let path = dir.template_path(name, "custom")?;
+                let data = read(&path).await?;
+                let template = verify_template(name, &data, None)?;
+                cprint!(Cyan, "Name: {}", &template.name);
+                cprint!(Cyan, "Description: {}", &template.description);
+                cprint!(Cyan, "Version: {}", &template.version);
+                cprint!(Cyan, "Path: {}", path.display());
+            }
+            TemplateCommands::Lint { name, repo } => {
+                let repo = repo.as_deref().unwrap_or("custom");
+                // a missing template is still an error, but a broken one is a problem to report
+                dir.template_path(name, repo)?;
+                let problems = match dir.search(name, repo).await {
+                    Ok(template) => lint_template(&template).await?,
+                    Err(_) => {
+                        cprint!(Red, "[invalid] `{name}` couldn't be parsed as a template");
+                        1
+                    }
+                };
+                if problems > 0 {
+                    return Err(Error::LintFailed(problems));
+                }
+                cprint!(Green, "No problems found in `{name}`!");
+            }
+            TemplateCommands::Diff { a, b } => {
+                let old = load_ref(a).await?;
+                let new = load_ref(b).await?;
+                diff_templates(old, new, a, b).await;
+            }
+            TemplateCommands::Pack { name, assets, output, force } => {
+                let output = bundle::pack(name, assets, output.clone(), *force).await?;
+                cprint!(Green, "Successfully packed `{name}` into `{}`!", output.display());
+            }
+            TemplateCommands::Install { source, name, store: store_name, force } => {
+                if source.starts_with(store::GIT_PREFIX) {
+                    store::install(source, store_name.as_deref(), *force).await?;
+                } else {
+                    let name = bundle::install(Path::new(source), name.as_deref(), *force).await?;
+                    cprint!(Green, "Successfully installed `{name}` to the custom repo!");
+                }
+            }
+            TemplateCommands::Update { store: store_name } => store::update(store_name.as_deref()).await?,
+            TemplateCommands::Import { file, name, description, body, stub, force } => {
+                let name = match name {
+                    Some(n) => n.to_string(),
+                    None => file_stem(file)?,
+                };
+                let latex = read_to_string(file).await?;
+                let document = parse_document(&latex)
+                    .ok_or(Error::InvalidInput(file.display().to_string()))?;
+                let description = description
+                    .clone()
+                    .unwrap_or_else(|| format!("Imported from {}", file.display()));
+                // the body is left out unless it is kept or replaced by a stub
+                let body = match (stub, body) {
+                    (Some(stub), _) => Some(stub.as_str()),
+                    (None, true) => Some(document.body.as_str()),
+                    (None, false) => None,
+                };
+                let template = import_template(&name, &description, &document, body);
+                dir.save_custom(&name, template.to_json_string().as_bytes(), *force).await?;
+                cprint!(Green, "Successfully imported `{}` as `{name}`!", file.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints a template's rendered main and structure files with a summary of its packages and elements
+pub async fn show_template(name: &str, repo: &str) -> Result<()> {
+    let template = dir()?.search(name, repo).await?;
+    let about = format!("{} ({}): {}", &template.name, &template.version, &template.description);
+    let elements: Vec<String> = element_counts(&template)
+        .into_iter()
+        .map(|(ty, count)| format!("{ty}: {count}"))
+        .collect();
+    let (main_data, str_data) = render(template).await;
+    cprint!(Magenta, "======{name}.tex======");
+    print_highlighted(&main_data);
+    cprint!(Magenta, "======include/structure.tex======");
+    print_highlighted(&str_data);
+    cprint!(Magenta, "======SUMMARY======");
+    cprint!(Cyan, "{about}");
+    let packages = packages(&str_data);
+    cprint!(Cyan, "Packages ({}): {}", packages.len(), packages.join(", "));
+    cprint!(Cyan, "Elements: {}", elements.join(", "));
+    Ok(())
+}
+
+/// Prints a unified diff of the LaTeX two templates render, followed by the changes to their packages and metadata
+pub async fn diff_templates(old: Template, new: Template, old_name: &str, new_name: &str) {
+    // rendering replaces the metadata, so the packages and metadata are read first
+    let old_packages = template_packages(&old);
+    let new_packages = template_packages(&new);
+    let old_meta = metadata_fields(&old);
+    let new_meta = metadata_fields(&new);
+    let (old_main, old_str) = render(old).await;
+    let (new_main, new_str) = render(new).await;
+    let mut changed = print_unified_diff(
+        &old_main,
+        &new_main,
+        &format!("{old_name}/main.tex"),
+        &format!("{new_name}/main.tex"),
+    );
+    changed |= print_unified_diff(
+        &old_str,
+        &new_str,
+        &format!("{old_name}/include/structure.tex"),
+        &format!("{new_name}/include/structure.tex"),
+    );
+    // packages that were added or removed
+    let removed: Vec<&String> = old_packages.iter().filter(|p| !new_packages.contains(p)).collect();
+    let added: Vec<&String> = new_packages.iter().filter(|p| !old_packages.contains(p)).collect();
+    if !removed.is_empty() || !added.is_empty() {
+        changed = true;
+        cprint!(Magenta, "======PACKAGES======");
+        for p in removed {
+            cprint!(Red, "- {p}");
+        }
+        for p in added {
+            cprint!(Green, "+ {p}");
+        }
+    }
+    // the template's description, version and every metadata field that changed
+    let fields: Vec<(&String, &String, &String)> = old_meta
+        .iter()
+        .filter_map(|(k, v)| new_meta.get(k).filter(|n| *n != v).map(|n| (k, v, n)))
+        .collect();
+    if !fields.is_empty() {
+        changed = true;
+        cprint!(Magenta, "======METADATA======");
+        for (field, old_value, new_value) in fields {
+            cprint!(Yellow, "{field}: {old_value} -> {new_value}");
+        }
+    }
+    if !changed {
+        cprint!(Green, "No differences between `{old_name}` and `{new_name}`.");
+    }
+}
+
+/// Loads a template from a `repo/name@version` reference, where the repo defaults to `mkproj`
+/// and a version can only be used with the `mkproj` repo
+pub async fn load_ref(reference: &str) -> Result<Template> {
+    let (repo, rest) = reference.split_once('/').unwrap_or(("mkproj", reference));
+    let (name, version) = match rest.split_once('@') {
+        Some((name, v)) => {
+            let v = v
+                .trim_start_matches('v')
+                .parse::<u64>()
+                .map_err(|_| Error::InvalidTemplate(reference.to_string()))?;
+            (name, Some(v))
+        }
+        None => (rest, None),
+    };
+    match version {
+        Some(num) if repo == "mkproj" => pinned_template(name, num).await,
+        Some(_) => Err(Error::InvalidRepo(repo.to_string())),
+        None => dir()?.search(name, repo).await,
+    }
+}
+
+// Renders a template's main and structure files the way a default config would
+async fn render(mut template: Template) -> (String, String) {
+    template.change_metadata(Metadata::default());
+    let input = Input::new(PathBuf::from("include").join("structure"), Level::Meta);
+    template.to_latex_split_string(input).await
+}
+
+// Returns a template's description, version and metadata fields as strings
+fn metadata_fields(template: &Template) -> BTreeMap<String, String> {
+    let mut fields = BTreeMap::new();
+    fields.insert("description".to_string(), template.description.to_string());
+    fields.insert("version".to_string(), template.version.to_string());
+    if let serde_json::Value::Object(meta) = &template_json(template)["element_list"]["metadata"] {
+        for (k, v) in meta {
+            // strings are shown without their quotes
+            let v = v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string());
+            fields.insert(k.to_string(), v);
+        }
+    }
+    fields
+}
+
+/// Returns the number of each type of element in a template, for example `Package: 3`
+pub fn element_counts(template: &Template) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for element in element_list(template) {
+        let ty = element["type_"].as_str().unwrap_or("Unknown");
+        *counts.entry(ty.trim_start_matches("T_").to_string()).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Returns the packages loaded by a template's elements, including `\usepackage` in custom elements
+pub fn template_packages(template: &Template) -> Vec<String> {
+    element_list(template)
+        .iter()
+        .filter_map(|element| element["latex"].as_str())
+        .flat_map(packages)
+        .collect()
+}
+
+/// A template in the custom repo or a store, as listed by `texcreate list --repo <repo>`
+#[derive(Serialize)]
+pub struct TemplateInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub version: Option<String>,
+    pub packages: Option<usize>,
+    /// Why the template couldn't be read, if it can't be used
+    pub error: Option<String>,
+}
+
+/// Returns every template in the custom repo or a store, keeping the ones that can't be parsed with their error
+pub async fn repo_templates(repo: &str) -> Result<Vec<TemplateInfo>> {
+    let dir = dir()?;
+    if repo != "custom" {
+        dir.check_store_name(repo)?;
+        if !dir.stores.join(repo).is_dir() {
+            return Err(Error::InvalidRepo(repo.to_string()));
+        }
+    }
+    let mut templates = Vec::new();
+    for (r, name) in dir.templates() {
+        if r != repo {
+            continue;
+        }
+        // a file whose name can't be used as a template, like `a@b.json`, is listed with the error
+        let result = match dir.template_path(&name, repo) {
+            Ok(path) => match read(&path).await {
+                Ok(data) => verify_template(&name, &data, None),
+                Err(e) => Err(Error::IO(e)),
+            },
+            Err(e) => Err(e),
+        };
+        let t = match result {
+            Ok(template) => TemplateInfo {
+                packages: Some(template_packages(&template).len()),
+                description: Some(template.description),
+                version: Some(template.version.to_string()),
+                name,
+                error: None,
+            },
+            Err(e) => TemplateInfo {
+                name,
+                description: None,
+                version: None,
+                packages: None,
+                error: Some(e.to_string()),
+            },
+        };
+        templates.push(t)
+    }
+    Ok(templates)
+}
+
+/// Displays the templates in the custom repo or a store like `repo.display()`, or as JSON for other tools
+pub async fn templates_display(repo: &str, json: bool) -> Result<()> {
+    let templates = repo_templates(repo).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&templates).unwrap());
+        return Ok(());
+    }
+    // to make sure that we print white, we will make stdout white
+    cprint!(White, "\r");
+    match repo {
+        "custom" => println!("TexCreate Custom Repo"),
+        _ => println!("TexCreate Store: {repo}"),
+    }
+    println!("Number of Templates: {}", templates.len());
+    println!("======TEMPLATES======");
+    for t in templates {
+        match (t.description, t.version, t.packages, t.error) {
+            (Some(desc), Some(version), Some(packages), None) => {
+                cprint!(White, "=> {} ({version}, {packages} packages): {desc}", t.name)
+            }
+            (_, _, _, error) => {
+                cprint!(Red, "=> {}: {}", t.name, error.unwrap_or_default());
+            }
+        }
+    }
+    cprint!(White, "=====================");
+    Ok(())
+}
+
+/// Prints every problem with a template's packages and returns the number found
+///
+/// Each package must be installed (found by `kpsewhich`), loaded once, and not conflict with
+/// another package or the document class.
+pub async fn lint_template(template: &Template) -> Result<usize> {
+    let mut problems = 0;
+    let packages = template_packages(template);
+    let mut seen = Vec::new();
+    for package in &packages {
+        if seen.contains(package) {
+            problems += 1;
+            cprint!(Red, "[duplicate] `{package}` is loaded more than once");
+            continue;
+        }
+        seen.push(package.to_string());
+        // `kpsewhich` prints the path of the package's `.sty` file, or nothing if it isn't installed
+        let output = Command::new("kpsewhich")
+            .arg(format!("{package}.sty"))
+            .output()
+            .await
+            .map_err(|_| Error::MissingProgram("kpsewhich".to_string()))?;
+        if !output.status.success() || output.stdout.is_empty() {
+            problems += 1;
+            cprint!(Red, "[missing] `{package}` isn't installed");
+        }
+    }
+    for (a, b, reason) in package_conflicts(&seen) {
+        problems += 1;
+        cprint!(Red, "[conflict] `{a}` and `{b}`: {reason}");
+    }
+    let class = template_json(template)["element_list"]["metadata"]["doc_class"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    for (package, reason) in class_conflicts(&class, &seen) {
+        problems += 1;
+        cprint!(Red, "[conflict] `{package}` and the `{class}` class: {reason}");
+    }
+    Ok(problems)
+}
+
+// Returns a template as a JSON value, to read the parts that are private to `Template`
+fn template_json(template: &Template) -> serde_json::Value {
+    serde_json::from_str(&template.to_json_string()).unwrap()
+}
+
+// Returns a template's elements as JSON values, since the element list is private to `Template`
+fn element_list(template: &Template) -> Vec<serde_json::Value> {
+    match template_json(template)["element_list"]["list"].take() {
+        serde_json::Value::Array(list) => list,
+        _ => Vec::new(),
+    }
+}
+
+// Returns the file name of a path without its extension
+fn file_stem(file: &Path) -> Result<String> {
+    file.file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .ok_or(Error::InvalidPath(file.display().to_string()))
+}
+
+// Creates a `Template` from a parsed LaTeX document and an optional body
+fn import_template(name: &str, description: &str, document: &TexDocument, body: Option<&str>) -> Template {
+    let mut metadata = Metadata {
+        doc_class: document.class.to_string(),
+        ..Default::default()
+    };
+    // a template's metadata only keeps the font size and paper size out of the class options
+    let mut unused = Vec::new();
+    for option in &document.class_options {
+        match option.strip_suffix("pt").and_then(|s| s.parse::<u8>().ok()) {
+            Some(size) => metadata.fontsize = size,
+            None if option.ends_with("paper") => metadata.papersize = option.to_string(),
+            None => unused.push(option.to_string()),
+        }
+    }
+    if !unused.is_empty() {
+        cprint!(
+            Yellow,
+            "The class options `{}` can't be kept, use `class_options` in the config instead.",
+            unused.join(",")
+        );
+    }
+    metadata.maketitle = document.body.contains(r"\maketitle");
+    let mut elements = Vec::new();
+    for statement in &document.preamble {
+        // the title, author and date are part of the metadata
+        if let Some((_, arg)) = command_args(statement, r"\title") {
+            metadata.title = arg;
+        } else if let Some((_, arg)) = command_args(statement, r"\author") {
+            metadata.author = arg;
+        } else if let Some((_, arg)) = command_args(statement, r"\date") {
+            metadata.date = arg;
+        } else {
+            elements.push(preamble_element(statement));
+        }
+    }
+    let mut template = Template::new(name, description, &metadata);
+    for element in elements {
+        template.push_element(element)
+    }
+    if let Some(body) = body {
+        // `\maketitle` is added by the metadata
+        let body = body.replacen(r"\maketitle", "", 1);
+        let body = body.trim();
+        if !body.is_empty() {
+            template.push_element(Element::from(Custom::new(body, Level::Document)));
+        }
+    }
+    template
+}
+
+// Returns the element for a preamble command, packages without options use `Package`
+fn preamble_element(statement: &str) -> Element<Any> {
+    match command_args(statement, r"\usepackage") {
+        Some((None, pkg)) if !pkg.contains(',') => Element::from(Package::new(&pkg)),
+        _ => Element::from(Custom::new(statement, Level::Packages)),
+    }
+}